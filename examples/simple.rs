@@ -127,5 +127,5 @@ fn setup(
             material: materials.add(Color::rgb(0.8, 0.7, 0.6).into()),
             ..Default::default()
         })
-        .insert(Shadowless);
+        .insert(NotShadowCaster);
 }
@@ -0,0 +1,220 @@
+/// A square region of a shadow map atlas texture, in texels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AtlasRect {
+    pub x: u32,
+    pub y: u32,
+    pub size: u32,
+}
+
+impl AtlasRect {
+    /// Offset and scale of the rect in uv space of an atlas with `atlas_size` texels per side,
+    /// in the layout the shaders expect. The last component is unused.
+    pub fn uv_rect(&self, atlas_size: u32) -> [f32; 4] {
+        let atlas_size = atlas_size as f32;
+
+        [
+            self.x as f32 / atlas_size,
+            self.y as f32 / atlas_size,
+            self.size as f32 / atlas_size,
+            0.0,
+        ]
+    }
+}
+
+/// Packs square shadow maps into a single 2D texture, so they can be rendered into directly.
+///
+/// The texture is a square grid of pages, with at least as many pages as requested.
+/// Every page is split like a quadtree, so requested resolutions are rounded up to
+/// the page size divided by a power of two.
+#[derive(Clone, Debug)]
+pub struct ShadowAtlas {
+    page_size: u32,
+    /// Number of pages per side of the texture.
+    columns: u32,
+    free: Vec<AtlasRect>,
+}
+
+impl ShadowAtlas {
+    pub fn new(page_size: u32, pages: u32) -> Self {
+        let mut columns = 1;
+        while columns * columns < pages {
+            columns += 1;
+        }
+
+        let mut atlas = Self {
+            page_size,
+            columns,
+            free: Vec::new(),
+        };
+        atlas.clear();
+        atlas
+    }
+
+    pub fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    /// Number of texels per side of the atlas texture.
+    pub fn texture_size(&self) -> u32 {
+        self.page_size * self.columns
+    }
+
+    /// Frees every allocated rect.
+    pub fn clear(&mut self) {
+        self.free = (0..self.columns * self.columns)
+            .map(|page| AtlasRect {
+                x: page % self.columns * self.page_size,
+                y: page / self.columns * self.page_size,
+                size: self.page_size,
+            })
+            .collect();
+    }
+
+    /// The size a request for `resolution` texels is rounded to.
+    pub fn rounded_size(&self, resolution: u32) -> u32 {
+        let mut size = self.page_size;
+        while size / 2 >= resolution.max(1) {
+            size /= 2;
+        }
+        size
+    }
+
+    /// Allocates a rect for every resolution or none at all.
+    pub fn allocate_all(&mut self, resolutions: &[u32]) -> Option<Vec<AtlasRect>> {
+        let free = self.free.clone();
+
+        let rects = resolutions
+            .iter()
+            .map(|resolution| self.allocate(*resolution))
+            .collect::<Option<Vec<_>>>();
+
+        if rects.is_none() {
+            self.free = free;
+        }
+
+        rects
+    }
+
+    pub fn allocate(&mut self, resolution: u32) -> Option<AtlasRect> {
+        let size = self.rounded_size(resolution);
+
+        // take the smallest free rect that fits to keep large rects available
+        let (index, _) = self
+            .free
+            .iter()
+            .enumerate()
+            .filter(|(_, rect)| rect.size >= size)
+            .min_by_key(|(_, rect)| (rect.size, rect.y, rect.x))?;
+
+        let mut rect = self.free.swap_remove(index);
+
+        while rect.size > size {
+            let half = rect.size / 2;
+
+            for (x, y) in [(half, 0), (0, half), (half, half)].iter() {
+                self.free.push(AtlasRect {
+                    x: rect.x + x,
+                    y: rect.y + y,
+                    size: half,
+                });
+            }
+
+            rect.size = half;
+        }
+
+        Some(rect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounded_size() {
+        let atlas = ShadowAtlas::new(1024, 1);
+
+        assert_eq!(atlas.rounded_size(1024), 1024);
+        assert_eq!(atlas.rounded_size(2048), 1024);
+        assert_eq!(atlas.rounded_size(513), 1024);
+        assert_eq!(atlas.rounded_size(512), 512);
+        assert_eq!(atlas.rounded_size(300), 512);
+        assert_eq!(atlas.rounded_size(1), 1);
+        assert_eq!(atlas.rounded_size(0), 1);
+    }
+
+    #[test]
+    fn grid_layout() {
+        let atlas = ShadowAtlas::new(256, 5);
+        assert_eq!(atlas.texture_size(), 768);
+
+        let atlas = ShadowAtlas::new(256, 4);
+        assert_eq!(atlas.texture_size(), 512);
+
+        let atlas = ShadowAtlas::new(256, 0);
+        assert_eq!(atlas.texture_size(), 256);
+    }
+
+    #[test]
+    fn pages_are_allocated_in_order() {
+        let mut atlas = ShadowAtlas::new(256, 4);
+        let rects = atlas.allocate_all(&[256; 4]).unwrap();
+
+        let mut origins = rects
+            .iter()
+            .map(|rect| (rect.x, rect.y))
+            .collect::<Vec<_>>();
+        origins.sort_unstable_by_key(|(x, y)| (*y, *x));
+        assert_eq!(origins, vec![(0, 0), (256, 0), (0, 256), (256, 256)]);
+    }
+
+    #[test]
+    fn split_into_quarters() {
+        let mut atlas = ShadowAtlas::new(256, 1);
+        let rects = atlas.allocate_all(&[128; 4]).unwrap();
+
+        let mut origins = rects
+            .iter()
+            .map(|rect| (rect.x, rect.y))
+            .collect::<Vec<_>>();
+        origins.sort_unstable_by_key(|(x, y)| (*y, *x));
+        assert_eq!(origins, vec![(0, 0), (128, 0), (0, 128), (128, 128)]);
+        assert!(rects.iter().all(|rect| rect.size == 128));
+
+        assert_eq!(atlas.allocate(1), None);
+    }
+
+    #[test]
+    fn small_rects_share_a_split() {
+        let mut atlas = ShadowAtlas::new(256, 2);
+        atlas.allocate(64).unwrap();
+
+        // the second page stays whole
+        assert_eq!(atlas.allocate(256).map(|rect| rect.size), Some(256));
+        assert_eq!(atlas.allocate(128).map(|rect| rect.size), Some(128));
+    }
+
+    #[test]
+    fn full() {
+        let mut atlas = ShadowAtlas::new(256, 1);
+        assert!(atlas.allocate(256).is_some());
+        assert_eq!(atlas.allocate(256), None);
+        assert_eq!(atlas.allocate(1), None);
+
+        atlas.clear();
+        assert!(atlas.allocate(256).is_some());
+    }
+
+    #[test]
+    fn allocate_all_rolls_back() {
+        let mut atlas = ShadowAtlas::new(256, 1);
+        atlas.allocate(128).unwrap();
+
+        assert_eq!(atlas.allocate_all(&[128, 128, 128, 128]), None);
+
+        // the failed request didn't take any of the remaining quarters
+        let rects = atlas.allocate_all(&[128, 128, 128]).unwrap();
+        assert!(rects.iter().all(|rect| rect.size == 128));
+        assert_eq!(atlas.allocate(1), None);
+    }
+}
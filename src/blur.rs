@@ -0,0 +1,350 @@
+use crate::{
+    atlas::AtlasRect,
+    prelude::DIRECTIONAL_LIGHT_MOMENTS_HANDLE,
+    render_graph::SHADOW_BLUR_PIPELINE,
+    shadow_pass_node::{
+        replay_render_commands, scratch_texture, set_viewport, LightKind, ShadowLights,
+    },
+};
+use bevy::{
+    core::bytes_of,
+    ecs::{system::BoxedSystem, world::World},
+    prelude::*,
+    render::{
+        draw::DrawContext,
+        pass::{LoadOp, Operations, PassDescriptor, RenderPassColorAttachment, TextureAttachment},
+        pipeline::{PipelineDescriptor, PipelineSpecialization},
+        render_graph::{CommandQueue, Node, ResourceSlotInfo, ResourceSlots, SystemNode},
+        renderer::{
+            BufferId, BufferInfo, BufferMapMode, BufferUsage, RenderContext, RenderResourceBinding,
+            RenderResourceBindings, RenderResourceContext, RenderResourceId, RenderResourceType,
+            SamplerId, TextureId,
+        },
+        texture::{SamplerDescriptor, TextureFormat, TextureUsage, TEXTURE_ASSET_INDEX},
+    },
+};
+use bytemuck::{Pod, Zeroable};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Separable gaussian blur of the moment shadow maps used by
+/// [`crate::prelude::ShadowFilter::Variance`] and the other moment based filters.
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowBlur {
+    /// Number of texels sampled on each side of a texel, 0 disables the blur.
+    pub radius: u32,
+    /// Standard deviation of the gaussian in texels.
+    pub sigma: f32,
+}
+
+impl Default for ShadowBlur {
+    fn default() -> Self {
+        Self {
+            radius: 2,
+            sigma: 1.0,
+        }
+    }
+}
+
+/// Reflects the ShadowBlurParams uniform in `shadow_blur.frag`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
+struct BlurParams {
+    source_rect: [f32; 4],
+    direction: [f32; 2],
+    texel_size: [f32; 2],
+    sigma: f32,
+    radius: i32,
+    _padding: [f32; 2],
+}
+
+/// One direction of the blur of a single shadow map.
+#[derive(Default)]
+struct BlurPass {
+    draw: Draw,
+    bindings: RenderResourceBindings,
+    staging_buffer: Option<BufferId>,
+    target: Option<TextureId>,
+    /// If set, the pass renders into this region of the moment atlas instead of the target.
+    rect: Option<AtlasRect>,
+}
+
+impl BlurPass {
+    fn bind(
+        &mut self,
+        render_resource_context: &dyn RenderResourceContext,
+        command_queue: &mut CommandQueue,
+        source: TextureId,
+        sampler: SamplerId,
+        params: &BlurParams,
+    ) {
+        const PARAMS_SIZE: usize = std::mem::size_of::<BlurParams>();
+
+        let staging_buffer = if let Some(staging_buffer) = self.staging_buffer {
+            render_resource_context.map_buffer(staging_buffer, BufferMapMode::Write);
+            staging_buffer
+        } else {
+            let staging_buffer = render_resource_context.create_buffer(BufferInfo {
+                size: PARAMS_SIZE,
+                buffer_usage: BufferUsage::COPY_SRC | BufferUsage::MAP_WRITE,
+                mapped_at_creation: true,
+            });
+
+            self.staging_buffer = Some(staging_buffer);
+            staging_buffer
+        };
+
+        let buffer = if let Some(RenderResourceBinding::Buffer { buffer, .. }) =
+            self.bindings.get("ShadowBlurParams")
+        {
+            *buffer
+        } else {
+            let buffer = render_resource_context.create_buffer(BufferInfo {
+                size: PARAMS_SIZE,
+                buffer_usage: BufferUsage::COPY_DST | BufferUsage::UNIFORM,
+                mapped_at_creation: false,
+            });
+
+            self.bindings.set(
+                "ShadowBlurParams",
+                RenderResourceBinding::Buffer {
+                    buffer,
+                    range: 0..PARAMS_SIZE as u64,
+                    dynamic_index: None,
+                },
+            );
+
+            buffer
+        };
+
+        render_resource_context.write_mapped_buffer(
+            staging_buffer,
+            0..PARAMS_SIZE as u64,
+            &mut |bytes, _| {
+                bytes.copy_from_slice(bytes_of(params));
+            },
+        );
+
+        render_resource_context.unmap_buffer(staging_buffer);
+
+        command_queue.copy_buffer_to_buffer(staging_buffer, 0, buffer, 0, PARAMS_SIZE as u64);
+
+        self.bindings
+            .set("ShadowBlurSource", RenderResourceBinding::Texture(source));
+        self.bindings
+            .set("ShadowBlurSampler", RenderResourceBinding::Sampler(sampler));
+    }
+
+    fn record(&mut self, draw_context: &mut DrawContext, pipeline: &Handle<PipelineDescriptor>) {
+        let specialization = PipelineSpecialization {
+            sample_count: 1,
+            ..Default::default()
+        };
+
+        self.draw.render_commands.clear();
+
+        draw_context
+            .set_pipeline(&mut self.draw, pipeline, &specialization)
+            .unwrap();
+
+        draw_context
+            .set_bind_groups_from_bindings(&mut self.draw, &mut [&mut self.bindings])
+            .unwrap();
+
+        self.draw.draw(0..3, 0..1);
+    }
+}
+
+/// Blur passes recorded by the blur system, rendered by [`ShadowBlurNode`].
+#[derive(Default)]
+pub struct ShadowBlurPasses {
+    passes: Vec<BlurPass>,
+}
+
+/// Blurs the moment shadow maps of directional lights, first horizontally out of the atlas
+/// into a scratch texture, then vertically back into the atlas region.
+pub struct ShadowBlurNode {
+    blur: ShadowBlur,
+    format: TextureFormat,
+    command_queue: CommandQueue,
+}
+
+impl ShadowBlurNode {
+    pub const TEXTURE: &'static str = "texture";
+
+    pub(crate) fn new(blur: ShadowBlur, format: TextureFormat) -> Self {
+        Self {
+            blur,
+            format,
+            command_queue: Default::default(),
+        }
+    }
+}
+
+impl Node for ShadowBlurNode {
+    fn input(&self) -> &[ResourceSlotInfo] {
+        &[ResourceSlotInfo {
+            name: Cow::Borrowed(Self::TEXTURE),
+            resource_type: RenderResourceType::Texture,
+        }]
+    }
+
+    fn update(
+        &mut self,
+        world: &World,
+        render_context: &mut dyn RenderContext,
+        input: &ResourceSlots,
+        _output: &mut ResourceSlots,
+    ) {
+        self.command_queue.execute(render_context);
+
+        let atlas_texture =
+            if let Some(RenderResourceId::Texture(atlas_texture)) = input.get(Self::TEXTURE) {
+                atlas_texture
+            } else {
+                return;
+            };
+
+        let blur_passes = world.get_resource::<ShadowBlurPasses>().unwrap();
+        let pipelines = world.get_resource::<Assets<PipelineDescriptor>>().unwrap();
+
+        for pass in &blur_passes.passes {
+            let target = match (pass.rect, pass.target) {
+                (Some(_), _) => atlas_texture,
+                (None, Some(target)) => target,
+                (None, None) => continue,
+            };
+
+            // the pass covers its whole region, so nothing has to be cleared
+            let desc = PassDescriptor {
+                color_attachments: vec![RenderPassColorAttachment {
+                    attachment: TextureAttachment::Id(target),
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+                sample_count: 1,
+            };
+
+            render_context.begin_pass(&desc, &pass.bindings, &mut |render_pass| {
+                if let Some(rect) = &pass.rect {
+                    set_viewport(render_pass, rect);
+                }
+                replay_render_commands(render_pass, &pass.draw, pipelines);
+            });
+        }
+    }
+}
+
+impl SystemNode for ShadowBlurNode {
+    fn get_system(&self) -> BoxedSystem {
+        let system = shadow_blur_system.system().config(|config| {
+            config.0 = Some(ShadowBlurSystemState {
+                blur: self.blur,
+                format: Some(self.format),
+                command_queue: self.command_queue.clone(),
+                ..Default::default()
+            });
+        });
+
+        Box::new(system)
+    }
+}
+
+#[derive(Default)]
+pub struct ShadowBlurSystemState {
+    blur: ShadowBlur,
+    format: Option<TextureFormat>,
+    sampler: Option<SamplerId>,
+    horizontal_targets: HashMap<u32, TextureId>,
+    command_queue: CommandQueue,
+}
+
+fn shadow_blur_system(
+    mut state: Local<ShadowBlurSystemState>,
+    mut draw_context: DrawContext,
+    mut blur_passes: ResMut<ShadowBlurPasses>,
+    lights: Res<ShadowLights>,
+    render_resource_context: Res<Box<dyn RenderResourceContext>>,
+) {
+    let state = &mut *state;
+    let render_resource_context = &**render_resource_context;
+    let pipeline = SHADOW_BLUR_PIPELINE.typed();
+
+    let (format, atlas_texture) = match (
+        state.format,
+        render_resource_context
+            .get_asset_resource_untyped(DIRECTIONAL_LIGHT_MOMENTS_HANDLE, TEXTURE_ASSET_INDEX)
+            .and_then(|resource| resource.get_texture()),
+    ) {
+        (Some(format), Some(atlas_texture)) => (format, atlas_texture),
+        _ => return,
+    };
+
+    // the passes only take discrete steps, so no filtering is needed
+    let sampler = *state.sampler.get_or_insert_with(|| {
+        render_resource_context.create_sampler(&SamplerDescriptor::default())
+    });
+
+    let usage = TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::SAMPLED;
+    let atlas_size = lights.atlas_size(LightKind::Directional) as f32;
+    let rects = lights
+        .rendered_rects(LightKind::Directional)
+        .collect::<Vec<_>>();
+
+    blur_passes
+        .passes
+        .resize_with(rects.len() * 2, Default::default);
+
+    for (rect, passes) in rects.iter().zip(blur_passes.passes.chunks_mut(2)) {
+        let horizontal_target = scratch_texture(
+            &mut state.horizontal_targets,
+            render_resource_context,
+            rect.size,
+            format,
+            usage,
+        );
+
+        let horizontal = BlurParams {
+            source_rect: rect.uv_rect(atlas_size as u32),
+            direction: [1.0, 0.0],
+            texel_size: [1.0 / atlas_size; 2],
+            sigma: state.blur.sigma,
+            radius: state.blur.radius as i32,
+            ..Default::default()
+        };
+
+        passes[0].bind(
+            render_resource_context,
+            &mut state.command_queue,
+            atlas_texture,
+            sampler,
+            &horizontal,
+        );
+        passes[0].record(&mut draw_context, &pipeline);
+        passes[0].target = Some(horizontal_target);
+        passes[0].rect = None;
+
+        // the horizontal target holds the whole region, so the vertical pass reads all of it
+        let vertical = BlurParams {
+            source_rect: [0.0, 0.0, 1.0, 0.0],
+            direction: [0.0, 1.0],
+            texel_size: [1.0 / rect.size as f32; 2],
+            ..horizontal
+        };
+
+        passes[1].bind(
+            render_resource_context,
+            &mut state.command_queue,
+            horizontal_target,
+            sampler,
+            &vertical,
+        );
+        passes[1].record(&mut draw_context, &pipeline);
+        passes[1].target = None;
+        passes[1].rect = Some(*rect);
+    }
+}
@@ -1,8 +1,12 @@
+use crate::frustum::ShadowCamera;
 use crate::shadow_pass_node::*;
 use bevy::prelude::*;
-use bevy::render::camera::{CameraProjection, OrthographicProjection};
+use bevy::render::camera::{
+    Camera, CameraProjection, OrthographicProjection, PerspectiveProjection,
+};
 use bevy::render::pipeline::PrimitiveTopology;
-use bevy_mod_bounding::{sphere, Bounded};
+use bevy::render::render_graph::base::camera::CAMERA_3D;
+use bevy_mod_bounding::{sphere, Bounded, BoundingVolume};
 
 const HALF_SIZE: f32 = 25.0;
 const MIN_BIAS: f32 = 0.00001;
@@ -23,6 +27,27 @@ pub struct ShadowDirectionalLight {
     pub far: f32,
     /// Min/max bias used when comparing the fragment's light space depth with the shadow map depth.
     pub bias: Vec2,
+    /// If set, the camera frustum is split into cascades that each get their own shadow map,
+    /// and the bounds above are only used for the near/far planes.
+    pub cascades: Option<ShadowCascades>,
+    /// What the projection is fit to when there are no cascades.
+    pub fit: DirectionalShadowFit,
+    /// Where the light space, and with it the bounds above, is centered.
+    pub center: DirectionalShadowCenter,
+    /// Up axis of the light space. If None, or parallel to the light direction,
+    /// Y is used unless the light points straight up or down, in which case Z is used.
+    pub up: Option<Vec3>,
+    /// Angular diameter of the light source in radians, e.g. about 0.0093 for the sun.
+    /// Used by [`crate::prelude::ShadowFilter::Pcss`] to soften shadows away from their casters.
+    pub angular_diameter: f32,
+    /// If true, the shadow map texels are kept at fixed world positions so shadow edges don't
+    /// shimmer when the light or camera moves. Projections are snapped to texel increments and
+    /// cascades are fit to a bounding sphere, whose size doesn't change when the camera rotates,
+    /// at the cost of some resolution.
+    pub stabilize: bool,
+    /// Resolution of every cascade in the shadow atlas,
+    /// if None [`crate::ShadowPlugin::directional_light_resolution`] is used.
+    pub resolution: Option<u32>,
 }
 
 impl Default for ShadowDirectionalLight {
@@ -35,6 +60,104 @@ impl Default for ShadowDirectionalLight {
             near: -20.0 * HALF_SIZE,
             far: 20.0 * HALF_SIZE,
             bias: Vec2::new(MIN_BIAS, MAX_BIAS),
+            cascades: None,
+            fit: DirectionalShadowFit::Bounds,
+            center: DirectionalShadowCenter::Origin,
+            up: None,
+            angular_diameter: 0.0,
+            stabilize: false,
+            resolution: None,
+        }
+    }
+}
+
+/// What the projection of a directional light without cascades covers.
+#[derive(Clone, Copy, Debug)]
+pub enum DirectionalShadowFit {
+    /// The left/right/bottom/top bounds of [`ShadowDirectionalLight`].
+    Bounds,
+    /// The view frustum of the 3d camera, so shadows follow the camera across the scene.
+    /// The bounds are only used for the near/far planes.
+    CameraFrustum {
+        /// Distance from the camera after which nothing receives shadows,
+        /// if None the far plane of the camera is used.
+        max_distance: Option<f32>,
+    },
+    /// The bounds of the meshes visible to the 3d camera, clipped to its view frustum, so none
+    /// of the shadow map is spent on what the camera can't see. This approximates sample
+    /// distribution shadow maps with the bounding spheres of the meshes instead of the depth buffer.
+    /// The bounds are updated when [`crate::ShadowPlugin::automatic_projection_bounds`] is enabled,
+    /// otherwise this is the same as [`DirectionalShadowFit::Bounds`].
+    SampleDistribution {
+        /// Distance from the camera after which meshes are ignored,
+        /// if None the far plane of the camera is used.
+        max_distance: Option<f32>,
+    },
+}
+
+/// Where the light space of a directional light is centered.
+#[derive(Clone, Copy, Debug)]
+pub enum DirectionalShadowCenter {
+    /// The world origin.
+    Origin,
+    /// The translation of the light's transform, so the shadowed region moves with the light entity.
+    Transform,
+}
+
+/// How the camera frustum is divided between cascades.
+#[derive(Clone, Copy, Debug)]
+pub enum CascadeSplit {
+    /// Splits are evenly spaced between the near plane and the max distance.
+    Uniform,
+    /// Splits grow logarithmically, giving the most resolution close to the camera.
+    Logarithmic,
+    /// Blend between [`CascadeSplit::Logarithmic`] (lambda = 1) and [`CascadeSplit::Uniform`] (lambda = 0).
+    Practical(f32),
+}
+
+impl CascadeSplit {
+    /// Returns the view space distance of split `i` out of `count` between `near` and `far`.
+    ///
+    /// Returns `None` for logarithmic and practical splits if `near` isn't positive,
+    /// they are undefined without a near plane in front of the camera.
+    pub fn split_distance(&self, i: u32, count: u32, near: f32, far: f32) -> Option<f32> {
+        let p = i as f32 / count as f32;
+        let uniform = near + (far - near) * p;
+
+        if let CascadeSplit::Uniform = *self {
+            return Some(uniform);
+        }
+
+        if near <= 0.0 {
+            return None;
+        }
+
+        let logarithmic = near * (far / near).powf(p);
+
+        match *self {
+            CascadeSplit::Practical(lambda) => {
+                Some(lambda * logarithmic + (1.0 - lambda) * uniform)
+            }
+            _ => Some(logarithmic),
+        }
+    }
+}
+
+pub struct ShadowCascades {
+    /// Number of cascades, clamped to [`crate::ShadowPlugin::max_cascades`].
+    pub count: u32,
+    /// How the camera frustum is divided between cascades.
+    pub split: CascadeSplit,
+    /// Distance from the camera after which nothing receives shadows.
+    pub max_distance: f32,
+}
+
+impl Default for ShadowCascades {
+    fn default() -> Self {
+        Self {
+            count: 4,
+            split: CascadeSplit::Practical(0.5),
+            max_distance: 100.0,
         }
     }
 }
@@ -42,6 +165,8 @@ impl Default for ShadowDirectionalLight {
 impl Light for DirectionalLight {
     type Config = ShadowDirectionalLight;
 
+    const KIND: LightKind = LightKind::Directional;
+
     fn proj_matrix(&self, config: Option<&Self::Config>) -> Mat4 {
         let left = config.map_or(-HALF_SIZE, |config| config.left);
         let right = config.map_or(HALF_SIZE, |config| config.right);
@@ -62,14 +187,183 @@ impl Light for DirectionalLight {
         .get_projection_matrix()
     }
 
-    fn view_matrix(&self) -> Mat4 {
-        let eye_position = -40.0 * self.get_direction();
-        Mat4::look_at_rh(eye_position, Vec3::ZERO, Vec3::Y)
+    fn view_matrix(&self, transform: &GlobalTransform, config: Option<&Self::Config>) -> Mat4 {
+        let center = match config.map_or(DirectionalShadowCenter::Origin, |config| config.center) {
+            DirectionalShadowCenter::Origin => Vec3::ZERO,
+            DirectionalShadowCenter::Transform => transform.translation,
+        };
+
+        let direction = self.get_direction();
+        let up = light_up(direction, config.and_then(|config| config.up));
+
+        let eye_position = center - 40.0 * direction;
+        Mat4::look_at_rh(eye_position, center, up)
     }
 
     fn shadow_bias_min_max(&self, config: Option<&Self::Config>) -> Vec2 {
         config.map_or(Vec2::new(MIN_BIAS, MAX_BIAS), |config| config.bias)
     }
+
+    fn shadow_resolution(&self, config: Option<&Self::Config>) -> Option<u32> {
+        config.and_then(|config| config.resolution)
+    }
+
+    fn shadow_view_count(&self, config: Option<&Self::Config>, max_cascades: usize) -> usize {
+        config
+            .and_then(|config| config.cascades.as_ref())
+            .map_or(1, |cascades| {
+                (cascades.count as usize).clamp(1, max_cascades.max(1))
+            })
+    }
+
+    fn light_size(&self, config: Option<&Self::Config>) -> f32 {
+        config.map_or(0.0, |config| 2.0 * (config.angular_diameter * 0.5).tan())
+    }
+
+    fn view_projections(
+        &self,
+        transform: &GlobalTransform,
+        config: Option<&Self::Config>,
+        camera: Option<&ShadowCamera>,
+        resolution: u32,
+        max_cascades: usize,
+    ) -> Vec<Mat4> {
+        let view = self.view_matrix(transform, config);
+        let stabilize = config.map_or(false, |config| config.stabilize);
+
+        // a frustum fit without cascades is a single uniform cascade, and the splits are
+        // computed for the cascades that fit into the atlas so the last one reaches max_distance
+        let fit = match (config, camera) {
+            (Some(config), Some(camera)) => match (&config.cascades, config.fit) {
+                (Some(cascades), _) => Some((
+                    config,
+                    camera,
+                    self.shadow_view_count(Some(config), max_cascades) as u32,
+                    cascades.split,
+                    Some(cascades.max_distance),
+                )),
+                (None, DirectionalShadowFit::CameraFrustum { max_distance }) => {
+                    Some((config, camera, 1, CascadeSplit::Uniform, max_distance))
+                }
+                // sample distribution bounds are fit by update_scene_bounding_box
+                (None, DirectionalShadowFit::Bounds)
+                | (None, DirectionalShadowFit::SampleDistribution { .. }) => None,
+            },
+            _ => None,
+        };
+
+        let (config, camera, count, split, max_distance) = match fit {
+            Some(fit) => fit,
+            None => {
+                let view_proj = self.proj_matrix(config) * view;
+
+                return if stabilize {
+                    vec![snap_to_texels(view_proj, resolution)]
+                } else {
+                    vec![view_proj]
+                };
+            }
+        };
+
+        let near = camera.near;
+        let far = max_distance.map_or(camera.far, |max_distance| camera.far.min(max_distance));
+
+        // uniform splits are always defined
+        let split = if near > 0.0 {
+            split
+        } else {
+            CascadeSplit::Uniform
+        };
+
+        (0..count)
+            .map(|i| {
+                let split_near = split.split_distance(i, count, near, far).unwrap();
+                let split_far = split.split_distance(i + 1, count, near, far).unwrap();
+
+                let corners = camera.frustum_corners(split_near, split_far);
+
+                let bb = if stabilize {
+                    bounding_sphere_box(&view, &corners)
+                } else {
+                    let mut bb = BoundingBox::min_max();
+                    for corner in corners.iter() {
+                        let corner_l = view.transform_point3(*corner);
+                        bb.min = bb.min.min(corner_l);
+                        bb.max = bb.max.max(corner_l);
+                    }
+                    bb
+                };
+
+                // casters between the light and the cascade are still inside the configured near/far
+                let proj = OrthographicProjection {
+                    left: bb.min.x,
+                    right: bb.max.x,
+                    bottom: bb.min.y,
+                    top: bb.max.y,
+                    near: config.near.min(-bb.max.z),
+                    far: config.far.max(-bb.min.z),
+                    ..Default::default()
+                }
+                .get_projection_matrix();
+
+                if stabilize {
+                    snap_to_texels(proj * view, resolution)
+                } else {
+                    proj * view
+                }
+            })
+            .collect()
+    }
+}
+
+/// Returns an up axis for a view looking along `direction`, `up` if it's usable.
+/// An up axis parallel to the view direction leaves the view basis undefined, which is NaN.
+fn light_up(direction: Vec3, up: Option<Vec3>) -> Vec3 {
+    // sine of the smallest angle between the up axis and the direction
+    const MIN_SIN: f32 = 0.001;
+
+    let direction = direction.normalize();
+
+    if let Some(up) = up {
+        if up.normalize().cross(direction).length() > MIN_SIN {
+            return up;
+        }
+    }
+
+    if Vec3::Y.cross(direction).length() > MIN_SIN {
+        Vec3::Y
+    } else {
+        Vec3::Z
+    }
+}
+
+/// Light space box around the bounding sphere of the corners. Unlike the box around the corners
+/// themselves its size doesn't change when the camera rotates.
+fn bounding_sphere_box(view: &Mat4, corners: &[Vec3; 8]) -> BoundingBox {
+    let center = corners.iter().fold(Vec3::ZERO, |sum, corner| sum + *corner) / 8.0;
+    let radius = corners
+        .iter()
+        .map(|corner| corner.distance(center))
+        .fold(0.0, f32::max);
+    // round the radius up so floating point noise doesn't change the size of the projection
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let center_l = view.transform_point3(center);
+
+    BoundingBox {
+        min: center_l - Vec3::splat(radius),
+        max: center_l + Vec3::splat(radius),
+    }
+}
+
+/// Offsets an orthographic view projection by less than a texel so the world origin lands on
+/// a texel corner, which keeps texels at fixed world positions while the projection moves.
+fn snap_to_texels(view_proj: Mat4, resolution: u32) -> Mat4 {
+    let half_resolution = resolution as f32 * 0.5;
+    let origin = view_proj.transform_point3(Vec3::ZERO) * half_resolution;
+    let offset = (origin.round() - origin) / half_resolution;
+
+    Mat4::from_translation(Vec3::new(offset.x, offset.y, 0.0)) * view_proj
 }
 
 pub fn add_bounding_spheres(
@@ -79,21 +373,53 @@ pub fn add_bounding_spheres(
         (Entity, &Handle<Mesh>),
         (
             Without<Shadowless>,
+            Without<ShadowProxy>,
             Without<sphere::BSphere>,
             Without<Bounded<sphere::BSphere>>,
         ),
     >,
+    proxies: Query<
+        (Entity, &ShadowProxy, &GlobalTransform, Option<&ProxySphere>),
+        (
+            Without<Shadowless>,
+            Or<(Changed<ShadowProxy>, Without<ProxySphere>)>,
+        ),
+    >,
 ) {
+    // meshes that aren't loaded yet are retried next frame
     for (entity, mesh_handle) in query.iter() {
-        let mesh = meshes.get(mesh_handle).unwrap();
-        if mesh.primitive_topology() == PrimitiveTopology::TriangleList {
-            commands
-                .entity(entity)
-                .insert(Bounded::<sphere::BSphere>::default());
+        if let Some(mesh) = meshes.get(mesh_handle) {
+            if mesh.primitive_topology() == PrimitiveTopology::TriangleList {
+                commands
+                    .entity(entity)
+                    .insert(Bounded::<sphere::BSphere>::default());
+            }
+        }
+    }
+
+    // bevy_mod_bounding only bounds the main mesh, so the spheres of proxies are added directly,
+    // as soon as the proxy mesh is loaded, and replace spheres of the main mesh
+    for (entity, proxy, transform, proxy_sphere) in proxies.iter() {
+        let mut entity_commands = commands.entity(entity);
+
+        match meshes.get(&proxy.0) {
+            Some(mesh) if mesh.primitive_topology() == PrimitiveTopology::TriangleList => {
+                entity_commands
+                    .remove::<Bounded<sphere::BSphere>>()
+                    .insert_bundle((sphere::BSphere::new(mesh, transform), ProxySphere));
+            }
+            // the sphere of a previous proxy is stale, retry once the new one is loaded
+            _ if proxy_sphere.is_some() => {
+                entity_commands.remove_bundle::<(sphere::BSphere, ProxySphere)>();
+            }
+            _ => {}
         }
     }
 }
 
+/// Marks a bounding sphere computed from the entity's [`ShadowProxy`].
+pub struct ProxySphere;
+
 struct BoundingBox {
     pub min: Vec3,
     pub max: Vec3,
@@ -106,38 +432,98 @@ impl BoundingBox {
             max: Vec3::splat(f32::MIN),
         }
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x
+    }
+
+    pub fn extend(&mut self, point: Vec3) {
+        self.min = self.min.min(point);
+        self.max = self.max.max(point);
+    }
+
+    pub fn extend_sphere(&mut self, center: Vec3, radius: f32) {
+        self.min = self.min.min(center - Vec3::splat(radius));
+        self.max = self.max.max(center + Vec3::splat(radius));
+    }
 }
 
 pub fn update_scene_bounding_box(
-    mut lights: Query<(&DirectionalLight, &mut ShadowDirectionalLight)>,
-    bounds: Query<(&GlobalTransform, &sphere::BSphere), (With<Handle<Mesh>>, Without<Shadowless>)>,
+    mut lights: Query<(
+        &DirectionalLight,
+        &GlobalTransform,
+        &mut ShadowDirectionalLight,
+    )>,
+    bounds: Query<
+        (
+            &GlobalTransform,
+            &sphere::BSphere,
+            Option<&NotShadowCaster>,
+            Option<&NotShadowReceiver>,
+            Option<&ShadowCasterOnly>,
+            Option<&Visible>,
+        ),
+        (With<Handle<Mesh>>, Without<Shadowless>),
+    >,
+    cameras: Query<(&Camera, &GlobalTransform, &PerspectiveProjection)>,
 ) {
-    for (dir_light, mut shadow_light) in lights.iter_mut() {
-        let view = dir_light.view_matrix();
+    let camera = cameras
+        .iter()
+        .find(|(camera, _, _)| camera.name.as_deref() == Some(CAMERA_3D))
+        .map(|(_, transform, projection)| ShadowCamera::new(transform, projection));
+
+    for (dir_light, light_transform, mut shadow_light) in lights.iter_mut() {
+        let view = dir_light.view_matrix(light_transform, Some(&*shadow_light));
+
+        // camera and depth range of the meshes fit by sample distribution
+        let visible_range = match (shadow_light.fit, camera) {
+            (DirectionalShadowFit::SampleDistribution { max_distance }, Some(camera)) => Some((
+                camera,
+                camera.near,
+                max_distance.map_or(camera.far, |max_distance| camera.far.min(max_distance)),
+            )),
+            _ => None,
+        };
+
         let mut bb = BoundingBox::min_max();
-        for (transform, bsphere) in bounds.iter() {
+        let mut visible_bb = BoundingBox::min_max();
+        for (transform, bsphere, not_caster, not_receiver, caster_only, visible) in bounds.iter() {
             let origin = bsphere.origin(*transform);
-            let origin_l = view * origin.extend(1.0);
+            let origin_l = view.transform_point3(origin);
             let radius = bsphere.radius(transform);
-            if origin_l.x - radius < bb.min.x {
-                bb.min.x = origin_l.x - radius;
-            }
-            if origin_l.y - radius < bb.min.y {
-                bb.min.y = origin_l.y - radius;
-            }
-            if origin_l.z - radius < bb.min.z {
-                bb.min.z = origin_l.z - radius;
-            }
-            if origin_l.x + radius > bb.max.x {
-                bb.max.x = origin_l.x + radius;
+
+            if not_caster.is_none() {
+                bb.extend_sphere(origin_l, radius);
             }
-            if origin_l.y + radius > bb.max.y {
-                bb.max.y = origin_l.y + radius;
+
+            // meshes that aren't drawn in the main pass don't receive shadows either
+            let receiver = not_receiver.is_none()
+                && caster_only.is_none()
+                && visible.map_or(true, |v| v.is_visible);
+
+            if let Some((camera, near, far)) = visible_range {
+                if receiver && camera.intersects_sphere(origin, radius, near, far) {
+                    visible_bb.extend_sphere(origin_l, radius);
+                }
             }
-            if origin_l.z + radius > bb.max.z {
-                bb.max.z = origin_l.z + radius;
+        }
+
+        // only the visible part of the scene needs to be covered by the shadow map,
+        // but every caster still extends the near/far planes
+        if let Some((camera, near, far)) = visible_range {
+            if !visible_bb.is_empty() {
+                let mut frustum_bb = BoundingBox::min_max();
+                for corner in camera.frustum_corners(near, far).iter() {
+                    frustum_bb.extend(view.transform_point3(*corner));
+                }
+
+                let min = visible_bb.min.max(frustum_bb.min);
+                let max = visible_bb.max.min(frustum_bb.max);
+                bb.min = min.truncate().extend(bb.min.z);
+                bb.max = max.truncate().extend(bb.max.z);
             }
         }
+
         shadow_light.left = bb.min.x;
         shadow_light.right = bb.max.x;
         shadow_light.bottom = bb.min.y;
@@ -148,3 +534,176 @@ pub fn update_scene_bounding_box(
         shadow_light.far = -bb.min.z;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light(direction: Vec3, up: Option<Vec3>) -> Mat4 {
+        let light = DirectionalLight::new(Color::WHITE, 1.0, direction);
+        let config = ShadowDirectionalLight {
+            up,
+            ..Default::default()
+        };
+
+        light.view_matrix(&GlobalTransform::identity(), Some(&config))
+    }
+
+    fn assert_finite(view: Mat4) {
+        assert!(
+            view.to_cols_array().iter().all(|v| v.is_finite()),
+            "{:?} isn't finite",
+            view
+        );
+    }
+
+    fn splits(split: CascadeSplit, near: f32, far: f32) -> Vec<f32> {
+        (0..=4)
+            .map(|i| split.split_distance(i, 4, near, far).unwrap())
+            .collect()
+    }
+
+    fn assert_close(a: &[f32], b: &[f32]) {
+        assert!(
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-3),
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn uniform_split() {
+        assert_close(
+            &splits(CascadeSplit::Uniform, 1.0, 9.0),
+            &[1.0, 3.0, 5.0, 7.0, 9.0],
+        );
+        assert_close(
+            &splits(CascadeSplit::Uniform, 0.0, 8.0),
+            &[0.0, 2.0, 4.0, 6.0, 8.0],
+        );
+    }
+
+    #[test]
+    fn logarithmic_split() {
+        assert_close(
+            &splits(CascadeSplit::Logarithmic, 1.0, 16.0),
+            &[1.0, 2.0, 4.0, 8.0, 16.0],
+        );
+    }
+
+    #[test]
+    fn practical_split() {
+        assert_close(
+            &splits(CascadeSplit::Practical(0.0), 1.0, 16.0),
+            &splits(CascadeSplit::Uniform, 1.0, 16.0),
+        );
+        assert_close(
+            &splits(CascadeSplit::Practical(1.0), 1.0, 16.0),
+            &splits(CascadeSplit::Logarithmic, 1.0, 16.0),
+        );
+        assert_close(
+            &splits(CascadeSplit::Practical(0.5), 1.0, 16.0),
+            &[1.0, 3.375, 6.25, 10.125, 16.0],
+        );
+    }
+
+    #[test]
+    fn non_positive_near() {
+        for near in [0.0, -1.0].iter() {
+            assert_eq!(
+                CascadeSplit::Logarithmic.split_distance(1, 4, *near, 100.0),
+                None
+            );
+            assert_eq!(
+                CascadeSplit::Practical(0.5).split_distance(1, 4, *near, 100.0),
+                None
+            );
+            assert!(CascadeSplit::Uniform
+                .split_distance(1, 4, *near, 100.0)
+                .is_some());
+        }
+    }
+
+    #[test]
+    fn default_cascades_reach_max_distance() {
+        let max_cascades = crate::ShadowPlugin::default().max_cascades();
+        let light = DirectionalLight::new(Color::WHITE, 1.0, Vec3::new(1.0, -1.0, 0.0));
+        let config = ShadowDirectionalLight {
+            cascades: Some(ShadowCascades::default()),
+            ..Default::default()
+        };
+        let camera = ShadowCamera::new(
+            &GlobalTransform::identity(),
+            &PerspectiveProjection::default(),
+        );
+
+        let view_count = light.shadow_view_count(Some(&config), max_cascades);
+        assert_eq!(view_count, max_cascades);
+
+        let view_projs = light.view_projections(
+            &GlobalTransform::identity(),
+            Some(&config),
+            Some(&camera),
+            1024,
+            max_cascades,
+        );
+        assert_eq!(view_projs.len(), view_count);
+
+        // the far corners of the last cascade lie on the max distance
+        let max_distance = ShadowCascades::default().max_distance;
+        let corners = camera.frustum_corners(camera.near, max_distance - 0.01);
+
+        for corner in corners.iter() {
+            let ndc = view_projs.last().unwrap().project_point3(*corner);
+            assert!(
+                ndc.x.abs() <= 1.0 + 1e-4
+                    && ndc.y.abs() <= 1.0 + 1e-4
+                    && (0.0..=1.0).contains(&ndc.z),
+                "{:?} is outside of the last cascade",
+                corner
+            );
+        }
+    }
+
+    #[test]
+    fn straight_down() {
+        assert_finite(light(-Vec3::Y, None));
+    }
+
+    #[test]
+    fn straight_up() {
+        assert_finite(light(Vec3::Y, None));
+    }
+
+    #[test]
+    fn nearly_straight_down() {
+        assert_finite(light(Vec3::new(0.0001, -1.0, 0.0), None));
+    }
+
+    #[test]
+    fn parallel_up_override() {
+        assert_finite(light(-Vec3::X, Some(Vec3::X)));
+    }
+
+    #[test]
+    fn up_override() {
+        let view = light(-Vec3::Y, Some(Vec3::X));
+        assert_finite(view);
+
+        // the up axis maps to +Y in light space
+        let up = view.transform_vector3(Vec3::X);
+        assert!((up - Vec3::Y).length() < 1e-5, "{:?}", up);
+    }
+
+    #[test]
+    fn view_looks_along_direction() {
+        for direction in [-Vec3::Y, Vec3::Y, Vec3::new(1.0, -1.0, 0.0)].iter() {
+            let view = light(*direction, None);
+
+            // light space looks along -Z
+            let forward = view.transform_vector3(direction.normalize());
+            assert!((forward + Vec3::Z).length() < 1e-5, "{:?}", forward);
+        }
+    }
+}
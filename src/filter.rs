@@ -0,0 +1,240 @@
+use bevy::render::{
+    color::Color,
+    pipeline::CompareFunction,
+    texture::{FilterMode, SamplerDescriptor, TextureFormat},
+};
+
+/// How the shadow pbr shader filters shadow map lookups.
+#[derive(Clone, Copy, Debug)]
+pub enum ShadowFilter {
+    /// A single depth comparison, gives hard stair-stepped edges.
+    Nearest,
+    /// A single lookup through a comparison sampler, which the hardware filters bilinearly.
+    Hardware,
+    /// An NxN grid of comparison lookups around the fragment.
+    Box { size: u32 },
+    /// 16 comparison lookups on a poisson disk with a radius in texels.
+    PoissonDisk { radius: f32 },
+    /// Like [`ShadowFilter::PoissonDisk`], but the disk is rotated by per-pixel noise,
+    /// trading banding for noise.
+    RotatedPoissonDisk { radius: f32 },
+    /// Percentage-closer soft shadows, the filter radius grows with the distance between
+    /// receiver and occluder and with [`crate::prelude::ShadowDirectionalLight::angular_diameter`].
+    /// `max_radius` in texels bounds both the blocker search and the filter.
+    Pcss { max_radius: f32 },
+    /// Variance shadow maps. The shadow pass stores the first two moments of the depth, which are
+    /// blurred by [`crate::ShadowPlugin::blur`] and tested with the Chebyshev upper bound.
+    /// `light_bleed_reduction` in [0, 1) cuts off the tail of the bound to hide light bleeding.
+    Variance {
+        min_variance: f32,
+        light_bleed_reduction: f32,
+    },
+    /// Exponential variance shadow maps, like [`ShadowFilter::Variance`] but the moments are taken
+    /// of a positively and a negatively warped depth, which bleeds far less light.
+    /// Exponents above 42 overflow the 32 bit float moments.
+    ExponentialVariance {
+        positive_exponent: f32,
+        negative_exponent: f32,
+        min_variance: f32,
+        light_bleed_reduction: f32,
+    },
+    /// Exponential shadow maps, the shadow pass stores `exp(exponent * depth)` and the visibility
+    /// is `exp(exponent * (occluder_depth - depth))`. The cheapest filterable technique, higher
+    /// exponents give sharper contact shadows but overflow 32 bit floats above 88.
+    Exponential { exponent: f32 },
+    /// Moment shadow maps, four moments of the depth are stored and the visibility is
+    /// reconstructed with the Hamburger 4MSM method, which bleeds less light than
+    /// [`ShadowFilter::Variance`]. With `quantize` the moments are transformed to keep their
+    /// precision in `Rgba16Float` instead of `Rgba32Float`, halving the memory. `moment_bias`
+    /// hides numerical artifacts, about 3e-5 unquantized and 6e-5 quantized.
+    Moments {
+        quantize: bool,
+        moment_bias: f32,
+        light_bleed_reduction: f32,
+    },
+}
+
+impl Default for ShadowFilter {
+    fn default() -> Self {
+        ShadowFilter::Nearest
+    }
+}
+
+impl ShadowFilter {
+    /// Shader defs selecting this filter in `shadows.glsl`.
+    pub fn shader_defs(&self) -> Vec<String> {
+        let mut defs = Vec::new();
+
+        if self.uses_comparison_sampler() {
+            defs.push("SHADOW_COMPARISON_SAMPLER".to_string());
+        }
+
+        if self.moment_format().is_some() && !self.moments_filterable() {
+            defs.push("SHADOW_MOMENTS_MANUAL_FILTER".to_string());
+        }
+
+        match *self {
+            ShadowFilter::Nearest | ShadowFilter::Hardware => {}
+            ShadowFilter::Box { size } => {
+                defs.push("SHADOW_FILTER_BOX".to_string());
+                defs.push(format!("SHADOW_FILTER_BOX_SIZE {}", size.max(1)));
+            }
+            ShadowFilter::PoissonDisk { radius } => {
+                defs.push("SHADOW_FILTER_POISSON".to_string());
+                defs.push(format!("SHADOW_FILTER_RADIUS {:?}", radius));
+            }
+            ShadowFilter::RotatedPoissonDisk { radius } => {
+                defs.push("SHADOW_FILTER_POISSON".to_string());
+                defs.push("SHADOW_FILTER_ROTATED".to_string());
+                defs.push(format!("SHADOW_FILTER_RADIUS {:?}", radius));
+            }
+            ShadowFilter::Pcss { max_radius } => {
+                defs.push("SHADOW_FILTER_PCSS".to_string());
+                defs.push(format!("SHADOW_FILTER_RADIUS {:?}", max_radius));
+            }
+            ShadowFilter::Variance {
+                min_variance,
+                light_bleed_reduction,
+            } => {
+                defs.push("SHADOW_MOMENTS".to_string());
+                defs.push("SHADOW_MOMENTS_VSM".to_string());
+                defs.push(format!("SHADOW_MIN_VARIANCE {:?}", min_variance));
+                defs.push(format!(
+                    "SHADOW_LIGHT_BLEED_REDUCTION {:?}",
+                    light_bleed_reduction
+                ));
+            }
+            ShadowFilter::ExponentialVariance {
+                positive_exponent,
+                negative_exponent,
+                min_variance,
+                light_bleed_reduction,
+            } => {
+                defs.push("SHADOW_MOMENTS".to_string());
+                defs.push("SHADOW_MOMENTS_EVSM".to_string());
+                defs.push(format!(
+                    "SHADOW_EVSM_POSITIVE_EXPONENT {:?}",
+                    positive_exponent
+                ));
+                defs.push(format!(
+                    "SHADOW_EVSM_NEGATIVE_EXPONENT {:?}",
+                    negative_exponent
+                ));
+                defs.push(format!("SHADOW_MIN_VARIANCE {:?}", min_variance));
+                defs.push(format!(
+                    "SHADOW_LIGHT_BLEED_REDUCTION {:?}",
+                    light_bleed_reduction
+                ));
+            }
+            ShadowFilter::Exponential { exponent } => {
+                defs.push("SHADOW_MOMENTS".to_string());
+                defs.push("SHADOW_MOMENTS_ESM".to_string());
+                defs.push(format!("SHADOW_ESM_EXPONENT {:?}", exponent));
+            }
+            ShadowFilter::Moments {
+                quantize,
+                moment_bias,
+                light_bleed_reduction,
+            } => {
+                defs.push("SHADOW_MOMENTS".to_string());
+                defs.push("SHADOW_MOMENTS_MSM".to_string());
+                if quantize {
+                    defs.push("SHADOW_MSM_QUANTIZED".to_string());
+                }
+                defs.push(format!("SHADOW_MOMENT_BIAS {:?}", moment_bias));
+                defs.push(format!(
+                    "SHADOW_LIGHT_BLEED_REDUCTION {:?}",
+                    light_bleed_reduction
+                ));
+            }
+        }
+
+        defs
+    }
+
+    /// The blocker search of PCSS needs the raw depth, so it can't use a comparison sampler.
+    /// Moment based filters don't sample the depth at all.
+    pub fn uses_comparison_sampler(&self) -> bool {
+        !matches!(self, ShadowFilter::Nearest | ShadowFilter::Pcss { .. })
+            && self.moment_format().is_none()
+    }
+
+    /// Format of the moment shadow maps, None if the filter compares depth directly.
+    pub fn moment_format(&self) -> Option<TextureFormat> {
+        match self {
+            ShadowFilter::Variance { .. } => Some(TextureFormat::Rg32Float),
+            ShadowFilter::ExponentialVariance { .. } => Some(TextureFormat::Rgba32Float),
+            ShadowFilter::Exponential { .. } => Some(TextureFormat::R32Float),
+            ShadowFilter::Moments { quantize: true, .. } => Some(TextureFormat::Rgba16Float),
+            ShadowFilter::Moments {
+                quantize: false, ..
+            } => Some(TextureFormat::Rgba32Float),
+            _ => None,
+        }
+    }
+
+    /// True if the moment shadow maps can be filtered by the sampler on every adapter. 32 bit float
+    /// formats need an optional feature for that, so they're filtered in the shader instead.
+    pub fn moments_filterable(&self) -> bool {
+        self.moment_format() == Some(TextureFormat::Rgba16Float)
+    }
+
+    /// Moments of a fragment on the far plane, what moment shadow maps are cleared to.
+    pub fn moment_clear_color(&self) -> Color {
+        match *self {
+            ShadowFilter::ExponentialVariance {
+                positive_exponent,
+                negative_exponent,
+                ..
+            } => {
+                // reflects evsm_warp in the shaders, depth 1.0 is warped to 1.0
+                let positive = positive_exponent.exp();
+                let negative = -(-negative_exponent).exp();
+
+                Color::rgba_linear(positive, positive * positive, negative, negative * negative)
+            }
+            ShadowFilter::Exponential { exponent } => {
+                let far = exponent.exp();
+
+                Color::rgba_linear(far, far, far, far)
+            }
+            // reflects msm_quantize in shadow_moments.frag applied to the moments of depth 1.0
+            ShadowFilter::Moments { quantize: true, .. } => {
+                Color::rgba_linear(1.0, 0.99755993, 0.8934375, 0.0)
+            }
+            _ => Color::rgba_linear(1.0, 1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Sampler used for the shadow map textures.
+    pub fn sampler(&self) -> SamplerDescriptor {
+        if self.uses_comparison_sampler() {
+            SamplerDescriptor {
+                mag_filter: FilterMode::Linear,
+                min_filter: FilterMode::Linear,
+                compare_function: Some(CompareFunction::LessEqual),
+                ..Default::default()
+            }
+        } else {
+            SamplerDescriptor {
+                ..Default::default()
+            }
+        }
+    }
+
+    /// Sampler used for the moment shadow maps. Unlike depth, moments can be averaged, but only
+    /// the [`ShadowFilter::moments_filterable`] formats are filtered by the sampler.
+    pub fn moment_sampler(&self) -> SamplerDescriptor {
+        let filter = if self.moments_filterable() {
+            FilterMode::Linear
+        } else {
+            FilterMode::Nearest
+        };
+
+        SamplerDescriptor {
+            mag_filter: filter,
+            min_filter: filter,
+            ..Default::default()
+        }
+    }
+}
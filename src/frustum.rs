@@ -0,0 +1,250 @@
+use bevy::prelude::*;
+use bevy::render::camera::PerspectiveProjection;
+
+/// The parts of the active 3d camera needed to fit shadow projections to what the camera sees.
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowCamera {
+    pub transform: GlobalTransform,
+    /// Vertical field of view in radians.
+    pub fov: f32,
+    pub aspect_ratio: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl ShadowCamera {
+    pub fn new(transform: &GlobalTransform, projection: &PerspectiveProjection) -> Self {
+        Self {
+            transform: *transform,
+            fov: projection.fov,
+            aspect_ratio: projection.aspect_ratio,
+            near: projection.near,
+            far: projection.far,
+        }
+    }
+
+    /// Returns the world space corners of the slice of the camera frustum between `near` and `far`.
+    /// The first four corners lie on the near plane, the last four on the far plane.
+    pub fn frustum_corners(&self, near: f32, far: f32) -> [Vec3; 8] {
+        let matrix = self.transform.compute_matrix();
+        let tan_half_fov = (self.fov * 0.5).tan();
+
+        let mut corners = [Vec3::ZERO; 8];
+
+        for (i, distance) in [near, far].iter().enumerate() {
+            let half_height = distance * tan_half_fov;
+            let half_width = half_height * self.aspect_ratio;
+
+            let plane = [
+                Vec3::new(-half_width, -half_height, -distance),
+                Vec3::new(half_width, -half_height, -distance),
+                Vec3::new(half_width, half_height, -distance),
+                Vec3::new(-half_width, half_height, -distance),
+            ];
+
+            for (j, corner) in plane.iter().enumerate() {
+                corners[i * 4 + j] = matrix.transform_point3(*corner);
+            }
+        }
+
+        corners
+    }
+
+    /// Returns true if a world space sphere intersects the slice of the camera frustum
+    /// between `near` and `far`. Spheres just outside the corners of the frustum are
+    /// considered intersecting as well.
+    pub fn intersects_sphere(&self, center: Vec3, radius: f32, near: f32, far: f32) -> bool {
+        // ignore scale, the planes are tested in world units
+        let view =
+            Mat4::from_rotation_translation(self.transform.rotation, self.transform.translation)
+                .inverse();
+        let center = view.transform_point3(center);
+        let distance = -center.z;
+
+        if distance + radius < near || distance - radius > far {
+            return false;
+        }
+
+        let tan_half_fov = (self.fov * 0.5).tan();
+        let tan_half_width = tan_half_fov * self.aspect_ratio;
+
+        // signed distances to the side planes, scaled by the length of the plane normals
+        center.x.abs() - distance * tan_half_width
+            <= radius * (1.0 + tan_half_width * tan_half_width).sqrt()
+            && center.y.abs() - distance * tan_half_fov
+                <= radius * (1.0 + tan_half_fov * tan_half_fov).sqrt()
+    }
+}
+
+/// The planes of a view projection's clip space volume, pointing inwards.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    pub fn from_view_proj(view_proj: &Mat4) -> Self {
+        // clip space depth ranges from 0 to w
+        Self::from_clip_bounds(view_proj, Vec3::new(-1.0, -1.0, 0.0), Vec3::ONE)
+    }
+
+    /// The box between `min` and `max` in normalized device coordinates of `view_proj`.
+    pub fn from_clip_bounds(view_proj: &Mat4, min: Vec3, max: Vec3) -> Self {
+        let row0 = view_proj.row(0);
+        let row1 = view_proj.row(1);
+        let row2 = view_proj.row(2);
+        let row3 = view_proj.row(3);
+
+        let mut planes = [
+            row0 - min.x * row3,
+            max.x * row3 - row0,
+            row1 - min.y * row3,
+            max.y * row3 - row1,
+            row2 - min.z * row3,
+            max.z * row3 - row2,
+        ];
+
+        for plane in planes.iter_mut() {
+            *plane /= plane.truncate().length();
+        }
+
+        Self { planes }
+    }
+
+    /// The part of the frustum of `view_proj` that can cast shadows onto the slice of the camera
+    /// frustum between `near` and `far`. This is the bounding box of the slice in normalized
+    /// device coordinates extruded towards the light, which is along the depth axis for both
+    /// orthographic and perspective projections. None if part of the slice is behind the light.
+    pub fn extruded(view_proj: &Mat4, camera: &ShadowCamera, near: f32, far: f32) -> Option<Self> {
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+
+        for corner in camera.frustum_corners(near, far).iter() {
+            let clip = *view_proj * corner.extend(1.0);
+
+            if clip.w <= 0.0 {
+                return None;
+            }
+
+            let ndc = clip.truncate() / clip.w;
+            min = min.min(ndc);
+            max = max.max(ndc);
+        }
+
+        Some(Self::from_clip_bounds(
+            view_proj,
+            Vec3::new(min.x.max(-1.0), min.y.max(-1.0), 0.0),
+            Vec3::new(max.x.min(1.0), max.y.min(1.0), max.z.min(1.0)),
+        ))
+    }
+
+    /// Returns false if a world space sphere lies entirely outside of the frustum.
+    pub fn intersects_sphere(&self, center: Vec3, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center) + plane.w >= -radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn camera() -> ShadowCamera {
+        ShadowCamera {
+            transform: GlobalTransform::identity(),
+            fov: FRAC_PI_2,
+            aspect_ratio: 1.0,
+            near: 1.0,
+            far: 100.0,
+        }
+    }
+
+    #[test]
+    fn orthographic_frustum_intersects_spheres() {
+        let frustum =
+            Frustum::from_view_proj(&Mat4::orthographic_rh(-1.0, 1.0, -1.0, 1.0, 0.0, 10.0));
+
+        assert!(frustum.intersects_sphere(Vec3::new(0.0, 0.0, -5.0), 0.5));
+        assert!(frustum.intersects_sphere(Vec3::new(1.2, 0.0, -5.0), 0.5));
+        assert!(frustum.intersects_sphere(Vec3::new(0.0, 0.0, 0.5), 1.0));
+        assert!(!frustum.intersects_sphere(Vec3::new(5.0, 0.0, -5.0), 1.0));
+        assert!(!frustum.intersects_sphere(Vec3::new(0.0, 0.0, 2.0), 1.0));
+        assert!(!frustum.intersects_sphere(Vec3::new(0.0, 0.0, -12.0), 1.0));
+    }
+
+    #[test]
+    fn perspective_frustum_intersects_spheres() {
+        let frustum = Frustum::from_view_proj(&Mat4::perspective_rh(FRAC_PI_2, 1.0, 1.0, 10.0));
+
+        assert!(frustum.intersects_sphere(Vec3::new(0.0, 0.0, -5.0), 1.0));
+        assert!(frustum.intersects_sphere(Vec3::new(5.5, 0.0, -5.0), 1.0));
+        assert!(frustum.intersects_sphere(Vec3::new(0.0, 0.0, -10.5), 1.0));
+        assert!(!frustum.intersects_sphere(Vec3::new(8.0, 0.0, -5.0), 1.0));
+        assert!(!frustum.intersects_sphere(Vec3::new(0.0, 0.0, -12.0), 1.0));
+        assert!(!frustum.intersects_sphere(Vec3::ZERO, 0.5));
+    }
+
+    #[test]
+    fn clip_bounds_shrink_frustum() {
+        let view_proj = Mat4::orthographic_rh(-10.0, 10.0, -10.0, 10.0, 0.0, 10.0);
+        let frustum = Frustum::from_clip_bounds(
+            &view_proj,
+            Vec3::new(-0.5, -0.5, 0.0),
+            Vec3::new(0.5, 0.5, 0.5),
+        );
+
+        assert!(frustum.intersects_sphere(Vec3::new(4.0, -4.0, -4.0), 0.5));
+        assert!(frustum.intersects_sphere(Vec3::new(5.5, 0.0, -2.0), 1.0));
+        assert!(!frustum.intersects_sphere(Vec3::new(7.0, 0.0, -2.0), 1.0));
+        assert!(!frustum.intersects_sphere(Vec3::new(0.0, 0.0, -7.0), 1.0));
+    }
+
+    #[test]
+    fn camera_intersects_spheres() {
+        let camera = camera();
+
+        assert!(camera.intersects_sphere(Vec3::new(0.0, 0.0, -5.0), 1.0, 1.0, 10.0));
+        assert!(camera.intersects_sphere(Vec3::new(5.5, 0.0, -5.0), 1.0, 1.0, 10.0));
+        assert!(camera.intersects_sphere(Vec3::new(0.0, 0.0, -10.5), 1.0, 1.0, 10.0));
+        assert!(!camera.intersects_sphere(Vec3::new(8.0, 0.0, -5.0), 1.0, 1.0, 10.0));
+        assert!(!camera.intersects_sphere(Vec3::new(0.0, 0.0, -20.0), 1.0, 1.0, 10.0));
+        assert!(!camera.intersects_sphere(Vec3::new(0.0, 0.0, 3.0), 1.0, 1.0, 10.0));
+    }
+
+    #[test]
+    fn extruded_frustum_keeps_casters_towards_the_light() {
+        let camera = camera();
+        // a directional light shining straight down onto the camera slice
+        let view_proj = Mat4::orthographic_rh(-20.0, 20.0, -20.0, 20.0, 0.0, 100.0)
+            * Mat4::look_at_rh(Vec3::new(0.0, 50.0, 0.0), Vec3::ZERO, Vec3::Z);
+        let frustum = Frustum::extruded(&view_proj, &camera, 1.0, 10.0).unwrap();
+
+        // above the slice, but its shadow falls into it
+        let caster = Vec3::new(0.0, 40.0, -5.0);
+        assert!(!camera.intersects_sphere(caster, 1.0, 1.0, 10.0));
+        assert!(frustum.intersects_sphere(caster, 1.0));
+
+        // inside the shadow map, but its shadow falls next to the slice
+        let beside = Vec3::new(15.0, 40.0, -5.0);
+        assert!(Frustum::from_view_proj(&view_proj).intersects_sphere(beside, 1.0));
+        assert!(!frustum.intersects_sphere(beside, 1.0));
+
+        // below the slice, its shadow points away from it
+        assert!(!frustum.intersects_sphere(Vec3::new(0.0, -40.0, -5.0), 1.0));
+    }
+
+    #[test]
+    fn extruded_frustum_behind_light() {
+        // a spot light inside the camera slice, looking away from the camera
+        let view_proj = Mat4::perspective_rh(FRAC_PI_2, 1.0, 0.1, 100.0)
+            * Mat4::look_at_rh(
+                Vec3::new(0.0, 0.0, -5.0),
+                Vec3::new(0.0, 0.0, -10.0),
+                Vec3::Y,
+            );
+
+        assert!(Frustum::extruded(&view_proj, &camera(), 1.0, 10.0).is_none());
+    }
+}
@@ -1,20 +1,49 @@
+mod atlas;
+mod blur;
 mod directional_light;
+mod filter;
+mod frustum;
+mod point_light;
 mod render_graph;
 mod shadow_pass_node;
+mod spot_light;
 
-use bevy::{prelude::*, transform::TransformSystem};
+use atlas::ShadowAtlas;
+use bevy::{
+    pbr::render_graph::{MAX_DIRECTIONAL_LIGHTS, MAX_POINT_LIGHTS},
+    prelude::*,
+    transform::TransformSystem,
+};
 use bevy_mod_bounding::{sphere, BoundingVolumePlugin};
-use shadow_pass_node::ShadowLights;
+use blur::ShadowBlur;
+use filter::ShadowFilter;
+use shadow_pass_node::{LightKind, ShadowLights, MAX_CASCADES, MAX_SPOT_LIGHTS, POINT_LIGHT_FACES};
+use spot_light::SpotLight;
 
 pub mod prelude {
-    pub use crate::directional_light::ShadowDirectionalLight;
-    pub use crate::render_graph::{DIRECTIONAL_LIGHT_DEPTH_HANDLE, SHADOW_PBR_PIPELINE};
-    pub use crate::shadow_pass_node::Shadowless;
+    pub use crate::blur::ShadowBlur;
+    pub use crate::directional_light::{
+        CascadeSplit, DirectionalShadowCenter, DirectionalShadowFit, ShadowCascades,
+        ShadowDirectionalLight,
+    };
+    pub use crate::filter::ShadowFilter;
+    pub use crate::point_light::ShadowPointLight;
+    pub use crate::render_graph::{
+        DIRECTIONAL_LIGHT_DEPTH_HANDLE, DIRECTIONAL_LIGHT_MOMENTS_HANDLE,
+        DIRECTIONAL_LIGHT_TRANSMITTANCE_DEPTH_HANDLE, DIRECTIONAL_LIGHT_TRANSMITTANCE_HANDLE,
+        POINT_LIGHT_DEPTH_HANDLE, SHADOWS_GLSL, SHADOW_PBR_PIPELINE, SPOT_LIGHT_DEPTH_HANDLE,
+    };
+    pub use crate::shadow_pass_node::{
+        NotShadowCaster, NotShadowReceiver, ShadowAlphaMask, ShadowCasterOnly, ShadowCasterStats,
+        ShadowLights, ShadowPipeline, ShadowProxy, ShadowTranslucent, Shadowless, JOINT_TRANSFORMS,
+    };
+    pub use crate::spot_light::{ShadowSpotLight, SpotLight};
     pub use crate::ShadowPlugin;
 }
 
 pub struct ShadowPlugin {
-    /// Resolution of directional light shadow maps.
+    /// Resolution of the directional light shadow atlas pages, and of every cascade
+    /// unless [`prelude::ShadowDirectionalLight::resolution`] requests a smaller one.
     pub directional_light_resolution: u32,
     /// If true, replaces the default pbr pipeline.
     /// If false use [`prelude::SHADOW_PBR_PIPELINE`].
@@ -25,9 +54,41 @@ pub struct ShadowPlugin {
     /// If false then the shadow pass won't be connected to main pass.
     pub connect_to_main_pass: bool,
     /// If true, automatically calculate the bounding box of the scene to use
-    /// for the directional light's orthographic projection.
+    /// for the directional light's orthographic projection, or of the part of the scene
+    /// visible to the camera with [`prelude::DirectionalShadowFit::SampleDistribution`].
     /// If false, use whatever is set in the ShadowDirectionalLight component.
     pub automatic_projection_bounds: bool,
+    /// If true, shadow casters outside of the frustum of a shadow map aren't drawn into it.
+    /// Uses bounding spheres, which are added to every mesh that casts shadows.
+    pub cull_casters: bool,
+    /// Max number of cascades per directional light. The atlas has a
+    /// `directional_light_resolution` sized page for every cascade of every light.
+    /// Clamped to 4.
+    pub max_cascades: u32,
+    /// How shadow map lookups are filtered in the shadow pbr pipeline.
+    pub filter: ShadowFilter,
+    /// Blur of the moment shadow maps, only used with moment based filters.
+    pub blur: ShadowBlur,
+    /// If true, casters with [`prelude::ShadowTranslucent`] tint the light of directional lights
+    /// by their base color instead of blocking it, using a transmittance atlas next to the depth.
+    /// If false they cast opaque shadows.
+    pub translucent_shadows: bool,
+    /// Resolution of the point light shadow atlas pages, and of every cube face
+    /// unless [`prelude::ShadowPointLight::resolution`] requests a smaller one.
+    pub point_light_resolution: u32,
+    /// Number of full resolution point light shadow maps that fit into the atlas,
+    /// which has six `point_light_resolution` sized pages for each of them.
+    /// Lights requesting a lower resolution share pages, so more of them fit.
+    /// Clamped to the max number of point lights supported by bevy.
+    pub max_point_light_shadows: u32,
+    /// Resolution of the spot light shadow atlas pages, and of every shadow map
+    /// unless [`prelude::ShadowSpotLight::resolution`] requests a smaller one.
+    pub spot_light_resolution: u32,
+    /// Number of full resolution spot light shadow maps that fit into the atlas,
+    /// which has a `spot_light_resolution` sized page for each of them.
+    /// Lights requesting a lower resolution share pages, so more of them fit.
+    /// Clamped to the max number of spot lights, 10.
+    pub max_spot_light_shadows: u32,
 }
 
 impl Default for ShadowPlugin {
@@ -38,6 +99,69 @@ impl Default for ShadowPlugin {
             create_pbr_pipeline: true,
             connect_to_main_pass: true,
             automatic_projection_bounds: false,
+            cull_casters: true,
+            max_cascades: 1,
+            filter: ShadowFilter::default(),
+            blur: ShadowBlur::default(),
+            translucent_shadows: false,
+            point_light_resolution: 512,
+            max_point_light_shadows: 4,
+            spot_light_resolution: 1024,
+            max_spot_light_shadows: 4,
+        }
+    }
+}
+
+impl ShadowPlugin {
+    pub(crate) fn max_cascades(&self) -> usize {
+        (self.max_cascades as usize).clamp(1, MAX_CASCADES)
+    }
+
+    /// Shader defs of the shadow pbr pipeline.
+    pub(crate) fn pbr_shader_defs(&self) -> Vec<String> {
+        let mut defs = self.filter.shader_defs();
+
+        if self.translucent_shadows {
+            defs.push("SHADOW_TRANSLUCENT".to_string());
+        }
+
+        defs
+    }
+
+    /// Prepares the source of a fragment shader that samples shadows like the shadow pbr pipeline.
+    /// Replaces `#include "shadows.glsl"` with [`prelude::SHADOWS_GLSL`] and defines the shader
+    /// defs matching the filter of this plugin. The library documents its bindings and
+    /// `shadow_visibility(world_pos, normal, light_index)`.
+    pub fn glsl_with_shadows(&self, source: &str) -> String {
+        render_graph::glsl_with_defs(
+            &render_graph::glsl_with_shadows_library(source),
+            &self.pbr_shader_defs(),
+        )
+    }
+
+    pub(crate) fn max_point_lights(&self) -> usize {
+        (self.max_point_light_shadows as usize).min(MAX_POINT_LIGHTS)
+    }
+
+    pub(crate) fn max_spot_lights(&self) -> usize {
+        (self.max_spot_light_shadows as usize).min(MAX_SPOT_LIGHTS)
+    }
+
+    /// The empty atlas of a light kind. Its pages are laid out in a square grid,
+    /// so the atlas texture is `resolution * ceil(sqrt(pages))` texels per side.
+    pub(crate) fn atlas(&self, kind: LightKind) -> ShadowAtlas {
+        match kind {
+            LightKind::Directional => ShadowAtlas::new(
+                self.directional_light_resolution,
+                (MAX_DIRECTIONAL_LIGHTS * self.max_cascades()) as u32,
+            ),
+            LightKind::Point => ShadowAtlas::new(
+                self.point_light_resolution,
+                (self.max_point_lights() * POINT_LIGHT_FACES) as u32,
+            ),
+            LightKind::Spot => {
+                ShadowAtlas::new(self.spot_light_resolution, self.max_spot_lights() as u32)
+            }
         }
     }
 }
@@ -46,23 +170,37 @@ impl Plugin for ShadowPlugin {
     fn build(&self, app: &mut AppBuilder) {
         render_graph::add_render_graph(self, app);
 
-        app.insert_resource(ShadowLights::default());
+        app.insert_resource(ShadowLights::new(self));
         app.add_system(
             shadow_pass_node::shadow_lights_register_system::<DirectionalLight>.system(),
         );
         app.add_system(shadow_pass_node::shadow_lights_remove_system::<DirectionalLight>.system());
-        if self.automatic_projection_bounds {
+        app.add_system(shadow_pass_node::shadow_lights_register_system::<PointLight>.system());
+        app.add_system(shadow_pass_node::shadow_lights_remove_system::<PointLight>.system());
+        app.add_system(shadow_pass_node::shadow_lights_register_system::<SpotLight>.system());
+        app.add_system(shadow_pass_node::shadow_lights_remove_system::<SpotLight>.system());
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            shadow_pass_node::not_shadow_receiver_system.system(),
+        );
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            shadow_pass_node::shadow_caster_only_system.system(),
+        );
+        if self.automatic_projection_bounds || self.cull_casters {
             app.add_plugin(BoundingVolumePlugin::<sphere::BSphere>::default())
                 .add_system_to_stage(
                     CoreStage::PreUpdate,
                     directional_light::add_bounding_spheres.system(),
-                )
-                .add_system_to_stage(
-                    CoreStage::PostUpdate,
-                    directional_light::update_scene_bounding_box
-                        .system()
-                        .after(TransformSystem::TransformPropagate),
                 );
         }
+        if self.automatic_projection_bounds {
+            app.add_system_to_stage(
+                CoreStage::PostUpdate,
+                directional_light::update_scene_bounding_box
+                    .system()
+                    .after(TransformSystem::TransformPropagate),
+            );
+        }
     }
 }
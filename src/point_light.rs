@@ -0,0 +1,97 @@
+use crate::frustum::ShadowCamera;
+use crate::shadow_pass_node::*;
+use bevy::prelude::*;
+
+const NEAR: f32 = 0.1;
+const MIN_BIAS: f32 = 0.01;
+const MAX_BIAS: f32 = 0.05;
+
+pub struct ShadowPointLight {
+    /// Near plane of the cube face projections.
+    pub near: f32,
+    /// Far plane of the cube face projections, if None the range of the [`PointLight`] is used.
+    pub range: Option<f32>,
+    /// Min/max bias in world units used when comparing the fragment's distance to the light
+    /// with the shadow map distance.
+    pub bias: Vec2,
+    /// Resolution of every cube face in the shadow atlas,
+    /// if None [`crate::ShadowPlugin::point_light_resolution`] is used.
+    pub resolution: Option<u32>,
+}
+
+impl Default for ShadowPointLight {
+    fn default() -> Self {
+        Self {
+            near: NEAR,
+            range: None,
+            bias: Vec2::new(MIN_BIAS, MAX_BIAS),
+            resolution: None,
+        }
+    }
+}
+
+/// Direction and up vector of each cube face, in the order of the views of a point light.
+fn cube_faces() -> [(Vec3, Vec3); POINT_LIGHT_FACES] {
+    [
+        (Vec3::X, Vec3::Y),
+        (-Vec3::X, Vec3::Y),
+        (Vec3::Y, Vec3::Z),
+        (-Vec3::Y, -Vec3::Z),
+        (Vec3::Z, Vec3::Y),
+        (-Vec3::Z, Vec3::Y),
+    ]
+}
+
+impl Light for PointLight {
+    type Config = ShadowPointLight;
+
+    const KIND: LightKind = LightKind::Point;
+
+    fn proj_matrix(&self, config: Option<&Self::Config>) -> Mat4 {
+        let near_far = self.depth_range(config);
+
+        Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, near_far.x, near_far.y)
+    }
+
+    /// View of the first cube face, see [`Light::view_projections`] for all of them.
+    fn view_matrix(&self, transform: &GlobalTransform, _config: Option<&Self::Config>) -> Mat4 {
+        let (direction, up) = cube_faces()[0];
+        Mat4::look_at_rh(transform.translation, transform.translation + direction, up)
+    }
+
+    fn shadow_bias_min_max(&self, config: Option<&Self::Config>) -> Vec2 {
+        config.map_or(Vec2::new(MIN_BIAS, MAX_BIAS), |config| config.bias)
+    }
+
+    fn shadow_resolution(&self, config: Option<&Self::Config>) -> Option<u32> {
+        config.and_then(|config| config.resolution)
+    }
+
+    fn shadow_view_count(&self, _config: Option<&Self::Config>, _max_cascades: usize) -> usize {
+        POINT_LIGHT_FACES
+    }
+
+    fn depth_range(&self, config: Option<&Self::Config>) -> Vec2 {
+        let near = config.map_or(NEAR, |config| config.near);
+        let far = config.and_then(|config| config.range).unwrap_or(self.range);
+
+        Vec2::new(near, far)
+    }
+
+    fn view_projections(
+        &self,
+        transform: &GlobalTransform,
+        config: Option<&Self::Config>,
+        _camera: Option<&ShadowCamera>,
+        _resolution: u32,
+        _max_cascades: usize,
+    ) -> Vec<Mat4> {
+        let proj = self.proj_matrix(config);
+        let eye = transform.translation;
+
+        cube_faces()
+            .iter()
+            .map(|(direction, up)| proj * Mat4::look_at_rh(eye, eye + *direction, *up))
+            .collect()
+    }
+}
@@ -1,29 +1,127 @@
-use crate::shadow_pass_node::LightsNode;
-use crate::shadow_pass_node::{ShadowLightsBindNode, ShadowPassNode};
-use bevy::pbr::render_graph::{MAX_DIRECTIONAL_LIGHTS, PBR_PIPELINE_HANDLE};
+use crate::blur::{ShadowBlurNode, ShadowBlurPasses};
+use crate::shadow_pass_node::{LightKind, LightsNode};
+use crate::shadow_pass_node::{ShadowAlphaMask, ShadowLightsBindNode, ShadowPassNode};
+use crate::spot_light::SpotLight;
+use bevy::pbr::render_graph::PBR_PIPELINE_HANDLE;
 use bevy::prelude::*;
 use bevy::reflect::TypeUuid;
-use bevy::render::pipeline::{Face, PipelineDescriptor, PrimitiveState};
+use bevy::render::pipeline::{
+    BlendComponent, BlendFactor, BlendOperation, BlendState, ColorTargetState, ColorWrite, Face,
+    PipelineDescriptor, PrimitiveState,
+};
 use bevy::render::shader::{ShaderStage, ShaderStages};
 use bevy::render::texture::TextureDescriptor;
 use bevy::render::{
-    render_graph::{base, RenderGraph, TextureNode},
-    texture::{Extent3d, SamplerDescriptor, TextureDimension, TextureFormat, TextureUsage},
+    render_graph::{base, RenderGraph, RenderResourcesNode, TextureNode},
+    texture::{
+        Extent3d, FilterMode, SamplerDescriptor, TextureDimension, TextureFormat, TextureUsage,
+    },
 };
 
 pub const DIRECTIONAL_LIGHT_DEPTH_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Texture::TYPE_UUID, 4328462394);
 
+pub const POINT_LIGHT_DEPTH_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Texture::TYPE_UUID, 7823549876123);
+
+pub const SPOT_LIGHT_DEPTH_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Texture::TYPE_UUID, 1298374650912);
+
+pub const DIRECTIONAL_LIGHT_MOMENTS_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Texture::TYPE_UUID, 5619283746501);
+
+pub const DIRECTIONAL_LIGHT_TRANSMITTANCE_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Texture::TYPE_UUID, 2938475610293);
+
+pub const DIRECTIONAL_LIGHT_TRANSMITTANCE_DEPTH_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Texture::TYPE_UUID, 8401928374651);
+
 pub const SHADOW_PIPELINE: HandleUntyped =
     HandleUntyped::weak_from_u64(PipelineDescriptor::TYPE_UUID, 219384239876);
 
 pub const SHADOW_PBR_PIPELINE: HandleUntyped =
     HandleUntyped::weak_from_u64(PipelineDescriptor::TYPE_UUID, 983456781236);
 
+pub const SHADOW_MOMENTS_PIPELINE: HandleUntyped =
+    HandleUntyped::weak_from_u64(PipelineDescriptor::TYPE_UUID, 4736251908374);
+
+pub const SHADOW_BLUR_PIPELINE: HandleUntyped =
+    HandleUntyped::weak_from_u64(PipelineDescriptor::TYPE_UUID, 8273645019283);
+
+pub const SHADOW_MASKED_PIPELINE: HandleUntyped =
+    HandleUntyped::weak_from_u64(PipelineDescriptor::TYPE_UUID, 6192837465019);
+
+pub const SHADOW_MASKED_MOMENTS_PIPELINE: HandleUntyped =
+    HandleUntyped::weak_from_u64(PipelineDescriptor::TYPE_UUID, 3847561029384);
+
+pub const SHADOW_TRANSLUCENT_PIPELINE: HandleUntyped =
+    HandleUntyped::weak_from_u64(PipelineDescriptor::TYPE_UUID, 7463529108374);
+
+/// Format of the directional light transmittance atlas.
+pub(crate) const TRANSMITTANCE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
 pub const DIRECTIONAL_LIGHT_DEPTH: &str = "directional_light_texture";
 pub const DIRECTIONAL_LIGHTS_NODE: &str = "direction_lights_node";
+pub const POINT_LIGHT_DEPTH: &str = "point_light_texture";
+pub const POINT_LIGHTS_NODE: &str = "point_lights_node";
+pub const SPOT_LIGHT_DEPTH: &str = "spot_light_texture";
+pub const SPOT_LIGHTS_NODE: &str = "spot_lights_node";
 pub const SHADOW_LIGHTS_BIND_NODE: &str = "shadow_lights_bind_node";
 pub const SHADOW_PASS_NODE: &str = "shadow_pass_node";
+pub const DIRECTIONAL_LIGHT_MOMENTS: &str = "directional_light_moments";
+pub const SHADOW_BLUR_NODE: &str = "shadow_blur_node";
+pub const SHADOW_ALPHA_MASK_NODE: &str = "shadow_alpha_mask_node";
+pub const DIRECTIONAL_LIGHT_TRANSMITTANCE: &str = "directional_light_transmittance";
+pub const DIRECTIONAL_LIGHT_TRANSMITTANCE_DEPTH: &str = "directional_light_transmittance_depth";
+
+/// Source of the shadow sampling library, see [`crate::ShadowPlugin::glsl_with_shadows`].
+pub const SHADOWS_GLSL: &str = include_str!("shaders/shadows.glsl");
+
+/// Replaces every `#include "shadows.glsl"` line of `source` with [`SHADOWS_GLSL`].
+pub(crate) fn glsl_with_shadows_library(source: &str) -> String {
+    let mut glsl = String::with_capacity(source.len() + SHADOWS_GLSL.len());
+
+    for line in source.lines() {
+        if line.trim() == "#include \"shadows.glsl\"" {
+            glsl.push_str(SHADOWS_GLSL);
+        } else {
+            glsl.push_str(line);
+        }
+        glsl.push('\n');
+    }
+
+    glsl
+}
+
+/// Inserts a `#define` for every shader def right after the `#version` directive of `source`,
+/// or at the start if it has none.
+pub(crate) fn glsl_with_defs(source: &str, defs: &[String]) -> String {
+    // comments may precede the version directive, which has to come before any define
+    let mut version_end = 0;
+    let mut offset = 0;
+
+    for line in source.split_inclusive('\n') {
+        offset += line.len();
+
+        if line.trim_start().starts_with("#version") {
+            version_end = offset;
+            break;
+        }
+    }
+
+    let (version, body) = source.split_at(version_end);
+
+    let mut glsl = version.to_string();
+
+    for def in defs {
+        glsl.push_str("#define ");
+        glsl.push_str(def);
+        glsl.push('\n');
+    }
+
+    glsl.push_str(body);
+    glsl
+}
 
 pub(crate) fn add_render_graph(shadow_plugin: &crate::ShadowPlugin, app: &mut AppBuilder) {
     let mut shaders = app
@@ -43,11 +141,154 @@ pub(crate) fn add_render_graph(shadow_plugin: &crate::ShadowPlugin, app: &mut Ap
             ..Default::default()
         },
         ..PipelineDescriptor::default_config(ShaderStages {
-            vertex,
+            vertex: vertex.clone(),
             fragment: None,
         })
     };
 
+    // alpha masked casters pass their uvs on to a fragment stage that discards masked fragments
+    let mask_defs = vec!["SHADOW_ALPHA_MASK".to_string()];
+
+    let masked_vertex = shaders.add(Shader::from_glsl(
+        ShaderStage::Vertex,
+        &glsl_with_defs(include_str!("shaders/shadow.vert"), &mask_defs),
+    ));
+
+    let mask_fragment = shaders.add(Shader::from_glsl(
+        ShaderStage::Fragment,
+        include_str!("shaders/shadow_mask.frag"),
+    ));
+
+    // masked casters are usually single sided quads, which would be culled when facing the light
+    let masked_pipeline = PipelineDescriptor {
+        color_target_states: vec![],
+        primitive: PrimitiveState {
+            cull_mode: None,
+            ..Default::default()
+        },
+        ..PipelineDescriptor::default_config(ShaderStages {
+            vertex: masked_vertex.clone(),
+            fragment: Some(mask_fragment),
+        })
+    };
+
+    // translucent casters are drawn after the opaque ones, testing against their depth without
+    // writing it and multiplying the transmitted light. The depth of the closest one is rendered
+    // separately by the depth pipelines, since float color targets aren't precise enough
+    let translucent_pipeline = if shadow_plugin.translucent_shadows {
+        let fragment = shaders.add(Shader::from_glsl(
+            ShaderStage::Fragment,
+            include_str!("shaders/shadow_translucent.frag"),
+        ));
+
+        let mut translucent_pipeline = PipelineDescriptor {
+            color_target_states: vec![ColorTargetState {
+                format: TRANSMITTANCE_FORMAT,
+                blend: Some(BlendState {
+                    color: BlendComponent {
+                        src_factor: BlendFactor::Zero,
+                        dst_factor: BlendFactor::Src,
+                        operation: BlendOperation::Add,
+                    },
+                    alpha: BlendComponent {
+                        src_factor: BlendFactor::Zero,
+                        dst_factor: BlendFactor::Src,
+                        operation: BlendOperation::Add,
+                    },
+                }),
+                write_mask: ColorWrite::ALL,
+            }],
+            primitive: PrimitiveState {
+                cull_mode: None,
+                ..Default::default()
+            },
+            ..PipelineDescriptor::default_config(ShaderStages {
+                vertex: masked_vertex.clone(),
+                fragment: Some(fragment),
+            })
+        };
+
+        if let Some(depth_stencil) = &mut translucent_pipeline.depth_stencil {
+            depth_stencil.depth_write_enabled = false;
+        }
+
+        Some(translucent_pipeline)
+    } else {
+        None
+    };
+
+    let moment_format = shadow_plugin.filter.moment_format();
+
+    // moment based filters render the moments of the depth into a color target,
+    // which is blurred before being sampled by the pbr pipeline
+    let moment_pipelines = moment_format.map(|format| {
+        let color_target_states = vec![ColorTargetState {
+            format,
+            blend: None,
+            write_mask: ColorWrite::ALL,
+        }];
+
+        let fragment = shaders.add(Shader::from_glsl(
+            ShaderStage::Fragment,
+            &glsl_with_defs(
+                include_str!("shaders/shadow_moments.frag"),
+                &shadow_plugin.filter.shader_defs(),
+            ),
+        ));
+
+        let moments_pipeline = PipelineDescriptor {
+            color_target_states: color_target_states.clone(),
+            primitive: PrimitiveState {
+                cull_mode: Some(Face::Front),
+                ..Default::default()
+            },
+            ..PipelineDescriptor::default_config(ShaderStages {
+                vertex,
+                fragment: Some(fragment),
+            })
+        };
+
+        let masked_fragment = shaders.add(Shader::from_glsl(
+            ShaderStage::Fragment,
+            &glsl_with_defs(
+                include_str!("shaders/shadow_moments.frag"),
+                &[shadow_plugin.filter.shader_defs(), mask_defs].concat(),
+            ),
+        ));
+
+        let masked_moments_pipeline = PipelineDescriptor {
+            color_target_states: color_target_states.clone(),
+            primitive: PrimitiveState {
+                cull_mode: None,
+                ..Default::default()
+            },
+            ..PipelineDescriptor::default_config(ShaderStages {
+                vertex: masked_vertex,
+                fragment: Some(masked_fragment),
+            })
+        };
+
+        let blur_vertex = shaders.add(Shader::from_glsl(
+            ShaderStage::Vertex,
+            include_str!("shaders/shadow_blur.vert"),
+        ));
+        let blur_fragment = shaders.add(Shader::from_glsl(
+            ShaderStage::Fragment,
+            include_str!("shaders/shadow_blur.frag"),
+        ));
+
+        let blur_pipeline = PipelineDescriptor {
+            color_target_states,
+            depth_stencil: None,
+            ..PipelineDescriptor::default_config(ShaderStages {
+                vertex: blur_vertex,
+                fragment: Some(blur_fragment),
+            })
+        };
+
+        (moments_pipeline, masked_moments_pipeline, blur_pipeline)
+    });
+
     // only create pbr pipeline if desired
     if shadow_plugin.create_pbr_pipeline {
         let vertex = shaders.add(Shader::from_glsl(
@@ -56,7 +297,7 @@ pub(crate) fn add_render_graph(shadow_plugin: &crate::ShadowPlugin, app: &mut Ap
         ));
         let fragment = shaders.add(Shader::from_glsl(
             ShaderStage::Fragment,
-            include_str!("shaders/shadow_pbr.frag"),
+            &shadow_plugin.glsl_with_shadows(include_str!("shaders/shadow_pbr.frag")),
         ));
 
         let shadow_pbr_pipeline = PipelineDescriptor::default_config(ShaderStages {
@@ -82,51 +323,213 @@ pub(crate) fn add_render_graph(shadow_plugin: &crate::ShadowPlugin, app: &mut Ap
         .unwrap();
 
     pipelines.set_untracked(SHADOW_PIPELINE, shadow_pipeline);
+    pipelines.set_untracked(SHADOW_MASKED_PIPELINE, masked_pipeline);
+
+    if let Some(translucent_pipeline) = translucent_pipeline {
+        pipelines.set_untracked(SHADOW_TRANSLUCENT_PIPELINE, translucent_pipeline);
+    }
+
+    if let Some((moments_pipeline, masked_moments_pipeline, blur_pipeline)) = moment_pipelines {
+        pipelines.set_untracked(SHADOW_MOMENTS_PIPELINE, moments_pipeline);
+        pipelines.set_untracked(SHADOW_MASKED_MOMENTS_PIPELINE, masked_moments_pipeline);
+        pipelines.set_untracked(SHADOW_BLUR_PIPELINE, blur_pipeline);
+    }
+
+    let blur = moment_format.filter(|_| shadow_plugin.blur.radius > 0);
+
+    if blur.is_some() {
+        app.world_mut().insert_resource(ShadowBlurPasses::default());
+    }
 
     let mut render_graph = app.world_mut().get_resource_mut::<RenderGraph>().unwrap();
 
-    let extent = Extent3d::new(
-        shadow_plugin.directional_light_resolution,
-        shadow_plugin.directional_light_resolution,
-        // this is wasteful if MAX_DIRECTIONAL_LIGHTS is 1
-        // but it will insure that the texture will always be
-        // interpreted as a D2Array texture rather than a D2
-        MAX_DIRECTIONAL_LIGHTS as u32,
-    );
+    let atlas_extent = |kind| {
+        let size = shadow_plugin.atlas(kind).texture_size();
+        Extent3d::new(size, size, 1)
+    };
+    let extent = atlas_extent(LightKind::Directional);
 
     render_graph.add_node(
         DIRECTIONAL_LIGHTS_NODE,
         LightsNode::<DirectionalLight>::default(),
     );
 
+    render_graph.add_node(POINT_LIGHTS_NODE, LightsNode::<PointLight>::default());
+
+    render_graph.add_node(SPOT_LIGHTS_NODE, LightsNode::<SpotLight>::default());
+
+    render_graph.add_system_node(SHADOW_PASS_NODE, ShadowPassNode::new(shadow_plugin));
+
     render_graph.add_system_node(
-        SHADOW_PASS_NODE,
-        ShadowPassNode::new(
-            MAX_DIRECTIONAL_LIGHTS as u32,
-            shadow_plugin.directional_light_resolution,
-        ),
+        SHADOW_ALPHA_MASK_NODE,
+        RenderResourcesNode::<ShadowAlphaMask>::new(false),
     );
 
+    render_graph
+        .add_node_edge(SHADOW_ALPHA_MASK_NODE, SHADOW_PASS_NODE)
+        .unwrap();
+
     render_graph.add_node(
         DIRECTIONAL_LIGHT_DEPTH,
         TextureNode::new(
             TextureDescriptor {
-                size: extent,
+                // moment based filters only sample the moments, so the depth is a placeholder
+                // that keeps the binding valid
+                size: if moment_format.is_some() {
+                    Extent3d::new(1, 1, 1)
+                } else {
+                    extent
+                },
                 sample_count: 1,
                 mip_level_count: 1,
                 dimension: TextureDimension::D2,
                 format: TextureFormat::Depth32Float,
-                usage: TextureUsage::OUTPUT_ATTACHMENT
-                    | TextureUsage::SAMPLED
-                    | TextureUsage::COPY_DST,
+                usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::SAMPLED,
             },
-            Some(SamplerDescriptor {
-                ..Default::default()
-            }),
+            Some(shadow_plugin.filter.sampler()),
             Some(DIRECTIONAL_LIGHT_DEPTH_HANDLE),
         ),
     );
 
+    render_graph.add_node(
+        POINT_LIGHT_DEPTH,
+        TextureNode::new(
+            TextureDescriptor {
+                size: atlas_extent(LightKind::Point),
+                sample_count: 1,
+                mip_level_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Depth32Float,
+                usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::SAMPLED,
+            },
+            // point light depth is linearized in the shader, so it can't use a comparison sampler
+            Some(SamplerDescriptor::default()),
+            Some(POINT_LIGHT_DEPTH_HANDLE),
+        ),
+    );
+
+    render_graph.add_node(
+        SPOT_LIGHT_DEPTH,
+        TextureNode::new(
+            TextureDescriptor {
+                size: atlas_extent(LightKind::Spot),
+                sample_count: 1,
+                mip_level_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Depth32Float,
+                usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::SAMPLED,
+            },
+            // spot light depth is linearized in the shader, so it can't use a comparison sampler
+            Some(SamplerDescriptor::default()),
+            Some(SPOT_LIGHT_DEPTH_HANDLE),
+        ),
+    );
+
+    if let Some(format) = moment_format {
+        // there are no mips of the atlas, large soft shadows come from the blur instead
+        render_graph.add_node(
+            DIRECTIONAL_LIGHT_MOMENTS,
+            TextureNode::new(
+                TextureDescriptor {
+                    size: extent,
+                    sample_count: 1,
+                    mip_level_count: 1,
+                    dimension: TextureDimension::D2,
+                    format,
+                    usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::SAMPLED,
+                },
+                Some(shadow_plugin.filter.moment_sampler()),
+                Some(DIRECTIONAL_LIGHT_MOMENTS_HANDLE),
+            ),
+        );
+
+        render_graph
+            .add_slot_edge(
+                DIRECTIONAL_LIGHT_MOMENTS,
+                TextureNode::TEXTURE,
+                SHADOW_PASS_NODE,
+                ShadowPassNode::MOMENT_TEXTURE,
+            )
+            .unwrap();
+    }
+
+    if shadow_plugin.translucent_shadows {
+        render_graph.add_node(
+            DIRECTIONAL_LIGHT_TRANSMITTANCE,
+            TextureNode::new(
+                TextureDescriptor {
+                    size: extent,
+                    sample_count: 1,
+                    mip_level_count: 1,
+                    dimension: TextureDimension::D2,
+                    format: TRANSMITTANCE_FORMAT,
+                    usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::SAMPLED,
+                },
+                Some(SamplerDescriptor {
+                    mag_filter: FilterMode::Linear,
+                    min_filter: FilterMode::Linear,
+                    ..Default::default()
+                }),
+                Some(DIRECTIONAL_LIGHT_TRANSMITTANCE_HANDLE),
+            ),
+        );
+
+        render_graph
+            .add_slot_edge(
+                DIRECTIONAL_LIGHT_TRANSMITTANCE,
+                TextureNode::TEXTURE,
+                SHADOW_PASS_NODE,
+                ShadowPassNode::TRANSMITTANCE_TEXTURE,
+            )
+            .unwrap();
+
+        // compared against the depth of receivers, so it's sampled without filtering
+        render_graph.add_node(
+            DIRECTIONAL_LIGHT_TRANSMITTANCE_DEPTH,
+            TextureNode::new(
+                TextureDescriptor {
+                    size: extent,
+                    sample_count: 1,
+                    mip_level_count: 1,
+                    dimension: TextureDimension::D2,
+                    format: TextureFormat::Depth32Float,
+                    usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::SAMPLED,
+                },
+                Some(SamplerDescriptor::default()),
+                Some(DIRECTIONAL_LIGHT_TRANSMITTANCE_DEPTH_HANDLE),
+            ),
+        );
+
+        render_graph
+            .add_slot_edge(
+                DIRECTIONAL_LIGHT_TRANSMITTANCE_DEPTH,
+                TextureNode::TEXTURE,
+                SHADOW_PASS_NODE,
+                ShadowPassNode::TRANSMITTANCE_DEPTH_TEXTURE,
+            )
+            .unwrap();
+    }
+
+    if let Some(format) = blur {
+        render_graph.add_system_node(
+            SHADOW_BLUR_NODE,
+            ShadowBlurNode::new(shadow_plugin.blur, format),
+        );
+
+        render_graph
+            .add_slot_edge(
+                DIRECTIONAL_LIGHT_MOMENTS,
+                TextureNode::TEXTURE,
+                SHADOW_BLUR_NODE,
+                ShadowBlurNode::TEXTURE,
+            )
+            .unwrap();
+
+        render_graph
+            .add_node_edge(SHADOW_PASS_NODE, SHADOW_BLUR_NODE)
+            .unwrap();
+    }
+
     render_graph.add_system_node(SHADOW_LIGHTS_BIND_NODE, ShadowLightsBindNode::default());
 
     render_graph
@@ -138,10 +541,36 @@ pub(crate) fn add_render_graph(shadow_plugin: &crate::ShadowPlugin, app: &mut Ap
         )
         .unwrap();
 
+    render_graph
+        .add_slot_edge(
+            POINT_LIGHT_DEPTH,
+            TextureNode::TEXTURE,
+            SHADOW_PASS_NODE,
+            ShadowPassNode::POINT_LIGHT_TEXTURE,
+        )
+        .unwrap();
+
+    render_graph
+        .add_slot_edge(
+            SPOT_LIGHT_DEPTH,
+            TextureNode::TEXTURE,
+            SHADOW_PASS_NODE,
+            ShadowPassNode::SPOT_LIGHT_TEXTURE,
+        )
+        .unwrap();
+
     render_graph
         .add_node_edge(base::node::MAIN_PASS, DIRECTIONAL_LIGHTS_NODE)
         .unwrap();
 
+    render_graph
+        .add_node_edge(base::node::MAIN_PASS, POINT_LIGHTS_NODE)
+        .unwrap();
+
+    render_graph
+        .add_node_edge(base::node::MAIN_PASS, SPOT_LIGHTS_NODE)
+        .unwrap();
+
     if shadow_plugin.connect_to_main_pass {
         render_graph
             .add_node_edge(SHADOW_LIGHTS_BIND_NODE, base::node::MAIN_PASS)
@@ -149,5 +578,30 @@ pub(crate) fn add_render_graph(shadow_plugin: &crate::ShadowPlugin, app: &mut Ap
         render_graph
             .add_node_edge(SHADOW_PASS_NODE, base::node::MAIN_PASS)
             .unwrap();
+
+        if blur.is_some() {
+            render_graph
+                .add_node_edge(SHADOW_BLUR_NODE, base::node::MAIN_PASS)
+                .unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defs_follow_version_directive() {
+        let defs = ["SHADOW_PCF".to_string(), "SHADOW_TRANSLUCENT".to_string()];
+
+        assert_eq!(
+            glsl_with_defs("// receives shadows\n#version 450\nvoid main() {}\n", &defs),
+            "// receives shadows\n#version 450\n#define SHADOW_PCF\n#define SHADOW_TRANSLUCENT\nvoid main() {}\n",
+        );
+        assert_eq!(
+            glsl_with_defs("void main() {}\n", &defs),
+            "#define SHADOW_PCF\n#define SHADOW_TRANSLUCENT\nvoid main() {}\n",
+        );
     }
 }
@@ -2,6 +2,17 @@
 
 layout(location = 0) in vec3 Vertex_Position;
 
+#ifdef SHADOW_ALPHA_MASK
+layout(location = 1) in vec2 Vertex_Uv;
+
+layout(location = 0) out vec2 v_Uv;
+#endif
+
+#ifdef SHADOW_SKINNED
+layout(location = 2) in vec4 Vertex_JointWeight;
+layout(location = 3) in uvec4 Vertex_JointIndex;
+#endif
+
 layout(set = 0, binding = 0) uniform ViewProj {
     mat4 view_proj;
 };
@@ -10,6 +21,27 @@ layout(set = 1, binding = 0) uniform Transform {
     mat4 model;
 };
 
+#ifdef SHADOW_SKINNED
+// world space joint matrices, which replace the model transform,
+// set by the app in the entity's bindings, see JOINT_TRANSFORMS in src/shadow_pass_node.rs
+layout(set = 1, binding = 1) readonly buffer JointTransforms {
+    mat4 Joints[];
+};
+#endif
+
 void main() {
-    gl_Position = view_proj * model * vec4(Vertex_Position, 1.0);
+#ifdef SHADOW_ALPHA_MASK
+    v_Uv = Vertex_Uv;
+#endif
+
+#ifdef SHADOW_SKINNED
+    mat4 world = Vertex_JointWeight.x * Joints[Vertex_JointIndex.x]
+               + Vertex_JointWeight.y * Joints[Vertex_JointIndex.y]
+               + Vertex_JointWeight.z * Joints[Vertex_JointIndex.z]
+               + Vertex_JointWeight.w * Joints[Vertex_JointIndex.w];
+#else
+    mat4 world = model;
+#endif
+
+    gl_Position = view_proj * world * vec4(Vertex_Position, 1.0);
 }
\ No newline at end of file
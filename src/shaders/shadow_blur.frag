@@ -0,0 +1,41 @@
+#version 450
+
+layout(location = 0) in vec2 v_Uv;
+
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 0, binding = 0) uniform ShadowBlurParams {
+    // uv offset and uv scale of the region being blurred, w is unused
+    vec4 SourceRect;
+    vec2 Direction;
+    // size of a texel of the source in uv space
+    vec2 TexelSize;
+    float Sigma;
+    int Radius;
+};
+
+layout(set = 0, binding = 1) uniform texture2D ShadowBlurSource;
+layout(set = 0, binding = 2) uniform sampler ShadowBlurSampler;
+
+vec4 sample_source(vec2 uv) {
+    // stay inside the region, so neighbouring shadow maps don't bleed in
+    vec2 half_texel = 0.5 * TexelSize;
+    uv = clamp(SourceRect.xy + uv * SourceRect.z, SourceRect.xy + half_texel, SourceRect.xy + SourceRect.z - half_texel);
+
+    return texture(sampler2D(ShadowBlurSource, ShadowBlurSampler), uv);
+}
+
+void main() {
+    // a step of one texel in the uv space of the region
+    vec2 uv_step = Direction * TexelSize / SourceRect.z;
+
+    vec4 sum = vec4(0.0);
+    float total_weight = 0.0;
+    for (int i = -Radius; i <= Radius; ++i) {
+        float weight = exp(-float(i * i) / (2.0 * Sigma * Sigma));
+        sum += weight * sample_source(v_Uv + float(i) * uv_step);
+        total_weight += weight;
+    }
+
+    o_Target = sum / total_weight;
+}
@@ -0,0 +1,33 @@
+#version 450
+
+// Discards the parts of alpha masked casters below the ShadowAlphaMask cutoff,
+// depth is written by the fixed function depth test.
+
+layout(location = 0) in vec2 v_Uv;
+
+layout(set = 2, binding = 0) uniform StandardMaterial_base_color {
+    vec4 base_color;
+};
+
+#ifdef STANDARDMATERIAL_BASE_COLOR_TEXTURE
+layout(set = 2, binding = 1) uniform texture2D StandardMaterial_base_color_texture;
+layout(set = 2, binding = 2) uniform sampler StandardMaterial_base_color_texture_sampler;
+#endif
+
+// set 1 holds the bindings of the entity like Transform, set 2 those of the material asset
+layout(set = 1, binding = 2) uniform ShadowAlphaMask_cutoff {
+    float cutoff;
+};
+
+void main() {
+    float alpha = base_color.a;
+#ifdef STANDARDMATERIAL_BASE_COLOR_TEXTURE
+    alpha *= texture(sampler2D(StandardMaterial_base_color_texture,
+                               StandardMaterial_base_color_texture_sampler),
+                     v_Uv).a;
+#endif
+
+    if (alpha < cutoff) {
+        discard;
+    }
+}
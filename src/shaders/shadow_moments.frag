@@ -0,0 +1,88 @@
+#version 450
+
+// Writes the moments of the fragment's depth for the moment based shadow filters,
+// shader defs are set by ShadowFilter::shader_defs in src/filter.rs.
+
+layout(location = 0) out vec4 o_Moments;
+
+#ifdef SHADOW_ALPHA_MASK
+// reflects shadow_mask.frag
+layout(location = 0) in vec2 v_Uv;
+
+layout(set = 2, binding = 0) uniform StandardMaterial_base_color {
+    vec4 base_color;
+};
+
+#    ifdef STANDARDMATERIAL_BASE_COLOR_TEXTURE
+layout(set = 2, binding = 1) uniform texture2D StandardMaterial_base_color_texture;
+layout(set = 2, binding = 2) uniform sampler StandardMaterial_base_color_texture_sampler;
+#    endif
+
+// set 1 holds the bindings of the entity like Transform, set 2 those of the material asset
+layout(set = 1, binding = 2) uniform ShadowAlphaMask_cutoff {
+    float cutoff;
+};
+#endif
+
+#ifdef SHADOW_MOMENTS_EVSM
+// reflects evsm_warp in shadows.glsl
+vec2 evsm_warp(float depth) {
+    depth = 2.0 * depth - 1.0;
+    return vec2(
+        exp(SHADOW_EVSM_POSITIVE_EXPONENT * depth),
+        -exp(-SHADOW_EVSM_NEGATIVE_EXPONENT * depth)
+    );
+}
+#endif
+
+#ifdef SHADOW_MSM_QUANTIZED
+// Optimized moment quantization from "Beyond Hard Shadows: Moment Shadow Maps for Single
+// Scattering, Soft Shadows and Translucent Occluders", Peters and Klein 2016,
+// reflected by msm_dequantize in shadows.glsl
+vec4 msm_quantize(vec4 moments) {
+    vec4 quantized = mat4(
+        -2.07224649, 13.7948857237, 0.105877704, 9.7924062118,
+        32.23703778, -59.4683975703, -1.9077466311, -33.7652110555,
+        -68.571074599, 82.0359750338, 9.3496555107, 47.9456096605,
+        39.3703274134, -35.364903257, -6.6543490743, -23.9728048165
+    ) * moments;
+    quantized.x += 0.035955884801;
+    return quantized;
+}
+#endif
+
+void main() {
+#ifdef SHADOW_ALPHA_MASK
+    float alpha = base_color.a;
+#    ifdef STANDARDMATERIAL_BASE_COLOR_TEXTURE
+    alpha *= texture(sampler2D(StandardMaterial_base_color_texture,
+                               StandardMaterial_base_color_texture_sampler),
+                     v_Uv).a;
+#    endif
+
+    if (alpha < cutoff) {
+        discard;
+    }
+#endif
+
+    float depth = gl_FragCoord.z;
+
+#if defined(SHADOW_MOMENTS_VSM)
+    // bias the second moment by the depth slope across the texel to reduce acne
+    float dx = dFdx(depth);
+    float dy = dFdy(depth);
+    o_Moments = vec4(depth, depth * depth + 0.25 * (dx * dx + dy * dy), 0.0, 0.0);
+#elif defined(SHADOW_MOMENTS_EVSM)
+    vec2 warped = evsm_warp(depth);
+    o_Moments = vec4(warped.x, warped.x * warped.x, warped.y, warped.y * warped.y);
+#elif defined(SHADOW_MOMENTS_ESM)
+    o_Moments = vec4(exp(SHADOW_ESM_EXPONENT * depth), 0.0, 0.0, 0.0);
+#elif defined(SHADOW_MOMENTS_MSM)
+    float depth_squared = depth * depth;
+    vec4 moments = vec4(depth, depth_squared, depth_squared * depth, depth_squared * depth_squared);
+#    ifdef SHADOW_MSM_QUANTIZED
+    moments = msm_quantize(moments);
+#    endif
+    o_Moments = moments;
+#endif
+}
@@ -34,29 +34,6 @@
 //
 // The above integration needs to be approximated.
 
-// reflects the constants defined bevy_pbr/src/render_graph/mod.rs
-const int MAX_POINT_LIGHTS = 10;
-const int MAX_DIRECTIONAL_LIGHTS = 1;
-
-struct PointLight {
-    vec4 pos;
-    vec4 color;
-    vec4 lightParams;
-};
- 
-struct DirectionalLight {
-    vec4 direction;
-    vec4 color;
-};
-
-struct ShadowDirectionalLight {
-    uint textureIndex;
-    vec3 pos;
-    vec2 shadow_bias_min_max;
-    vec2 _padding;
-    mat4 viewProj;
-};
-
 layout(location = 0) in vec3 v_WorldPosition;
 layout(location = 1) in vec3 v_WorldNormal;
 layout(location = 2) in vec2 v_Uv;
@@ -74,19 +51,7 @@ layout(std140, set = 0, binding = 1) uniform CameraPosition {
     vec4 CameraPos;
 };
 
-layout(std140, set = 1, binding = 0) uniform Lights {
-    vec4 AmbientColor;
-    uvec4 NumLights; // x = point lights, y = directional lights
-    PointLight PointLights[MAX_POINT_LIGHTS];
-    DirectionalLight DirectionalLights[MAX_DIRECTIONAL_LIGHTS];
-};
-
-layout(set = 1, binding = 1) uniform ShadowLights {
-    ShadowDirectionalLight shadow_directional_lights[MAX_DIRECTIONAL_LIGHTS];
-};
-
-layout(set = 1, binding = 2) uniform texture2DArray DirectionalLightTexture;
-layout(set = 1, binding = 3) uniform sampler DirectionalLightSampler;
+#include "shadows.glsl"
 
 layout(set = 3, binding = 0) uniform StandardMaterial_base_color {
     vec4 base_color;
@@ -298,7 +263,7 @@ vec3 reinhard_extended_luminance(vec3 color, float max_white_l) {
     return change_luminance(color, l_new);
 }
 
-vec3 point_light(PointLight light, float roughness, float NdotV, vec3 N, vec3 V, vec3 R, vec3 F0, vec3 diffuseColor) {
+vec3 point_light(PointLight light, ShadowPointLight shadow_light, float roughness, float NdotV, vec3 N, vec3 V, vec3 R, vec3 F0, vec3 diffuseColor) {
     vec3 light_to_frag = light.pos.xyz - v_WorldPosition.xyz;
     float distance_square = dot(light_to_frag, light_to_frag);
     float rangeAttenuation =
@@ -344,7 +309,9 @@ vec3 point_light(PointLight light, float roughness, float NdotV, vec3 N, vec3 V,
     // See https://google.github.io/filament/Filament.html#mjx-eqn-pointLightLuminanceEquation
     // TODO compensate for energy loss https://google.github.io/filament/Filament.html#materialsystem/improvingthebrdfs/energylossinspecularreflectance
     // light.color.rgb is premultiplied with light.intensity on the CPU
-    return ((diffuse + specular) * light.color.rgb) * (rangeAttenuation * NoL);
+    float shadow = point_shadow(shadow_light, v_WorldPosition, v_WorldNormal, light_to_frag);
+
+    return ((diffuse + specular) * light.color.rgb) * (rangeAttenuation * NoL * shadow);
 }
 
 vec3 dir_light(
@@ -371,6 +338,38 @@ vec3 dir_light(
     return (specular + diffuse) * light.color.rgb * NoL;
 }
 
+vec3 spot_light(
+    SpotLight light,
+    float roughness,
+    float NdotV,
+    vec3 N,
+    vec3 V,
+    vec3 R,
+    vec3 F0,
+    vec3 diffuseColor
+) {
+    vec3 frag_to_light = light.pos.xyz - v_WorldPosition.xyz;
+    float distance_square = dot(frag_to_light, frag_to_light);
+    float rangeAttenuation = getDistanceAttenuation(distance_square, light.pos.w);
+
+    vec3 L = normalize(frag_to_light);
+    // smooth falloff between the inner and outer cone
+    float cos_angle = dot(-L, light.direction.xyz);
+    float coneAttenuation = smoothstep(light.cos_inner_outer.y, light.cos_inner_outer.x, cos_angle);
+
+    vec3 H = normalize(L + V);
+    float NoL = saturate(dot(N, L));
+    float NoH = saturate(dot(N, H));
+    float LoH = saturate(dot(L, H));
+
+    vec3 diffuse = diffuseColor * Fd_Burley(roughness, NdotV, NoL, LoH);
+    vec3 specular = specular(F0, roughness, H, NdotV, NoL, NoH, LoH, 1.0);
+
+    float shadow = spot_shadow(light, v_WorldPosition, v_WorldNormal, frag_to_light);
+
+    return (diffuse + specular) * light.color.rgb * (rangeAttenuation * coneAttenuation * NoL * shadow);
+}
+
 #endif
 
 void main() {
@@ -440,32 +439,14 @@ void main() {
     // accumulate color
     vec3 light_accum = vec3(0.0);
     for (int i = 0; i < int(NumLights.x) && i < MAX_POINT_LIGHTS; ++i) {
-        light_accum += point_light(PointLights[i], roughness, NdotV, N, V, R, F0, diffuseColor);
+        light_accum += point_light(PointLights[i], shadow_point_lights[i], roughness, NdotV, N, V, R, F0, diffuseColor);
+    }
+    for (int i = 0; i < int(NumSpotLights.x) && i < MAX_SPOT_LIGHTS; ++i) {
+        light_accum += spot_light(SpotLights[i], roughness, NdotV, N, V, R, F0, diffuseColor);
     }
     for (int i = 0; i < int(NumLights.y) && i < MAX_DIRECTIONAL_LIGHTS; ++i) {
-        ShadowDirectionalLight shadow_light = shadow_directional_lights[i];
-
-        vec4 p = shadow_light.viewProj * vec4(v_WorldPosition, 1.0);
-        vec2 uv = p.xy;
-        uv.y *= -1.0;
-        uv += 1.0;
-        uv /= 2.0;
-
-        float depth = texture(
-            sampler2DArray(DirectionalLightTexture, DirectionalLightSampler), 
-            vec3(uv, shadow_light.textureIndex)
-        ).r;
-        float shadow_bias = max(
-            shadow_light.shadow_bias_min_max.y * (1.0 - dot(v_WorldNormal, DirectionalLights[i].direction.xyz)),
-            shadow_light.shadow_bias_min_max.x
-        );
-        // Clamping the light space z to the shadow map range prevents objects further away than the far plane
-        // from always being considered as occluded
-        if (clamp(p.z, 0.0, 1.0 + shadow_bias) - shadow_bias <= depth
-                || any(lessThan(uv, vec2(0.0)))
-                || any(greaterThan(uv, vec2(1.0)))) {
-            light_accum += dir_light(DirectionalLights[i], roughness, NdotV, N, V, R, F0, diffuseColor);
-        }
+        vec3 shadow = shadow_visibility(v_WorldPosition, v_WorldNormal, i);
+        light_accum += shadow * dir_light(DirectionalLights[i], roughness, NdotV, N, V, R, F0, diffuseColor);
     }
 
     vec3 diffuse_ambient = EnvBRDFApprox(diffuseColor, 1.0, NdotV);
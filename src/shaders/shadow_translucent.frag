@@ -0,0 +1,29 @@
+#version 450
+
+// Writes the light transmitted through translucent casters, blended multiplicatively with the
+// other translucent layers.
+
+layout(location = 0) in vec2 v_Uv;
+
+layout(location = 0) out vec4 o_Transmittance;
+
+layout(set = 2, binding = 0) uniform StandardMaterial_base_color {
+    vec4 base_color;
+};
+
+#ifdef STANDARDMATERIAL_BASE_COLOR_TEXTURE
+layout(set = 2, binding = 1) uniform texture2D StandardMaterial_base_color_texture;
+layout(set = 2, binding = 2) uniform sampler StandardMaterial_base_color_texture_sampler;
+#endif
+
+void main() {
+    vec4 color = base_color;
+#ifdef STANDARDMATERIAL_BASE_COLOR_TEXTURE
+    color *= texture(sampler2D(StandardMaterial_base_color_texture,
+                               StandardMaterial_base_color_texture_sampler),
+                     v_Uv);
+#endif
+
+    // a transparent layer lets all light through, an opaque one only its own color
+    o_Transmittance = vec4(mix(vec3(1.0), color.rgb, color.a), 1.0);
+}
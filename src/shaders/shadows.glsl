@@ -0,0 +1,571 @@
+// Shadow sampling library, shared by the shadow pbr pipeline and custom materials.
+//
+// Add `#include "shadows.glsl"` to a fragment shader and load it with
+// `ShadowPlugin::glsl_with_shadows`, which replaces the include with this file and defines
+// the shader defs of the plugin's filter. Declares the `Lights` and `ShadowLights` uniforms
+// and the shadow map textures in set 1, so the shader can't use set 1 for anything else.
+//
+// The main entry point is
+//
+//     vec3 shadow_visibility(vec3 world_pos, vec3 normal, int light_index)
+//
+// which returns the color of the light of `DirectionalLights[light_index]` reaching `world_pos`,
+// 0.0 if shadowed and 1.0 if lit, tinted by translucent casters when those are enabled.
+// `normal` is the normalized world space normal of the surface, used for the slope scaled bias.
+// Cascade selection, filtering and bias follow the light's configuration.
+// `point_shadow` and `spot_shadow` do the same for point and spot lights.
+
+#ifndef SHADOWS_GLSL
+#define SHADOWS_GLSL
+
+// reflects the constants defined bevy_pbr/src/render_graph/mod.rs
+const int MAX_POINT_LIGHTS = 10;
+const int MAX_DIRECTIONAL_LIGHTS = 1;
+// reflects MAX_CASCADES, POINT_LIGHT_FACES and MAX_SPOT_LIGHTS in src/shadow_pass_node.rs
+const int MAX_CASCADES = 4;
+const int POINT_LIGHT_FACES = 6;
+const int MAX_SPOT_LIGHTS = 10;
+
+const float SHADOW_PI = 3.141592653589793;
+
+struct PointLight {
+    vec4 pos;
+    vec4 color;
+    vec4 lightParams;
+};
+ 
+struct DirectionalLight {
+    vec4 direction;
+    vec4 color;
+};
+
+// atlas rects are the uv offset and uv scale of a shadow map in the atlas, w is unused
+
+struct ShadowDirectionalLight {
+    uint cascadeCount;
+    vec3 pos;
+    vec2 shadow_bias_min_max;
+    float light_size;
+    float _padding;
+    vec4 atlasRects[MAX_CASCADES];
+    mat4 viewProj[MAX_CASCADES];
+};
+
+struct ShadowPointLight {
+    // zero if the light has no shadow map
+    uint faceCount;
+    vec2 near_far;
+    vec2 shadow_bias_min_max;
+    vec2 _padding;
+    vec4 atlasRects[POINT_LIGHT_FACES];
+    mat4 viewProj[POINT_LIGHT_FACES];
+};
+
+struct SpotLight {
+    // w is the inverse of the range squared
+    vec4 pos;
+    vec4 direction;
+    vec4 color;
+    vec2 cos_inner_outer;
+    vec2 shadow_bias_min_max;
+    vec4 atlasRect;
+    // zero if the light has no shadow map
+    uint hasShadow;
+    vec2 near_far;
+    mat4 viewProj;
+};
+
+layout(std140, set = 1, binding = 0) uniform Lights {
+    vec4 AmbientColor;
+    uvec4 NumLights; // x = point lights, y = directional lights
+    PointLight PointLights[MAX_POINT_LIGHTS];
+    DirectionalLight DirectionalLights[MAX_DIRECTIONAL_LIGHTS];
+};
+
+layout(set = 1, binding = 1) uniform ShadowLights {
+    ShadowDirectionalLight shadow_directional_lights[MAX_DIRECTIONAL_LIGHTS];
+    ShadowPointLight shadow_point_lights[MAX_POINT_LIGHTS];
+    uvec4 NumSpotLights;
+    SpotLight SpotLights[MAX_SPOT_LIGHTS];
+};
+
+layout(set = 1, binding = 2) uniform texture2D DirectionalLightTexture;
+#ifdef SHADOW_COMPARISON_SAMPLER
+layout(set = 1, binding = 3) uniform samplerShadow DirectionalLightSampler;
+#else
+layout(set = 1, binding = 3) uniform sampler DirectionalLightSampler;
+#endif
+
+layout(set = 1, binding = 4) uniform texture2D PointLightTexture;
+layout(set = 1, binding = 5) uniform sampler PointLightSampler;
+
+layout(set = 1, binding = 6) uniform texture2D SpotLightTexture;
+layout(set = 1, binding = 7) uniform sampler SpotLightSampler;
+
+#ifdef SHADOW_MOMENTS
+layout(set = 1, binding = 8) uniform texture2D DirectionalLightMoments;
+layout(set = 1, binding = 9) uniform sampler DirectionalLightMomentsSampler;
+#endif
+
+#ifdef SHADOW_TRANSLUCENT
+// rgb is the light transmitted by translucent casters
+layout(set = 1, binding = 10) uniform texture2D DirectionalLightTransmittance;
+layout(set = 1, binding = 11) uniform sampler DirectionalLightTransmittanceSampler;
+// depth of the closest translucent caster, sampled without filtering
+layout(set = 1, binding = 12) uniform texture2D DirectionalLightTransmittanceDepth;
+layout(set = 1, binding = 13) uniform sampler DirectionalLightTransmittanceDepthSampler;
+#endif
+
+// Maps uv of a shadow map to its region of the atlas. The result is kept half a texel inside
+// the region, so filters don't read the neighbouring shadow maps.
+vec2 atlas_coords(vec2 uv, vec4 rect, vec2 texel_size) {
+    vec2 half_texel = 0.5 * texel_size;
+    return clamp(rect.xy + uv * rect.z, rect.xy + half_texel, rect.xy + rect.z - half_texel);
+}
+
+// Inverts a perspective depth to the linear distance along the view direction.
+float linearize_depth(float depth, vec2 near_far) {
+    float near = near_far.x;
+    float far = near_far.y;
+    return near * far / (far - depth * (far - near));
+}
+
+// Returns the visibility of the fragment from the point light, 0.0 if shadowed and 1.0 if lit.
+// Every cube face is a separate shadow map in the atlas, so the face is selected by hand.
+float point_shadow(ShadowPointLight shadow_light, vec3 world_pos, vec3 normal, vec3 frag_to_light) {
+#ifdef NOT_SHADOW_RECEIVER
+    return 1.0;
+#endif
+    if (shadow_light.faceCount == 0) {
+        return 1.0;
+    }
+
+    vec3 light_to_frag = -frag_to_light;
+    vec3 abs_light_to_frag = abs(light_to_frag);
+
+    // the face order reflects cube_faces in src/point_light.rs
+    uint face;
+    float frag_distance;
+    if (abs_light_to_frag.x >= abs_light_to_frag.y && abs_light_to_frag.x >= abs_light_to_frag.z) {
+        face = light_to_frag.x > 0.0 ? 0u : 1u;
+        frag_distance = abs_light_to_frag.x;
+    } else if (abs_light_to_frag.y >= abs_light_to_frag.z) {
+        face = light_to_frag.y > 0.0 ? 2u : 3u;
+        frag_distance = abs_light_to_frag.y;
+    } else {
+        face = light_to_frag.z > 0.0 ? 4u : 5u;
+        frag_distance = abs_light_to_frag.z;
+    }
+
+    if (frag_distance > shadow_light.near_far.y) {
+        return 1.0;
+    }
+
+    vec4 p = shadow_light.viewProj[face] * vec4(world_pos, 1.0);
+    vec2 uv = p.xy / p.w;
+    uv.y *= -1.0;
+    uv += 1.0;
+    uv /= 2.0;
+
+    float depth = texture(
+        sampler2D(PointLightTexture, PointLightSampler),
+        atlas_coords(uv, shadow_light.atlasRects[face], vec2(0.0))
+    ).r;
+    float occluder_distance = linearize_depth(depth, shadow_light.near_far);
+
+    float shadow_bias = max(
+        shadow_light.shadow_bias_min_max.y * (1.0 - dot(normal, normalize(frag_to_light))),
+        shadow_light.shadow_bias_min_max.x
+    );
+
+    return frag_distance - shadow_bias <= occluder_distance ? 1.0 : 0.0;
+}
+
+// Returns the visibility of the fragment from the spot light, 0.0 if shadowed and 1.0 if lit.
+float spot_shadow(SpotLight light, vec3 world_pos, vec3 normal, vec3 frag_to_light) {
+#ifdef NOT_SHADOW_RECEIVER
+    return 1.0;
+#endif
+    if (light.hasShadow == 0) {
+        return 1.0;
+    }
+
+    vec4 p = light.viewProj * vec4(world_pos, 1.0);
+    vec2 uv = p.xy / p.w;
+    uv.y *= -1.0;
+    uv += 1.0;
+    uv /= 2.0;
+
+    float depth = texture(
+        sampler2D(SpotLightTexture, SpotLightSampler),
+        atlas_coords(uv, light.atlasRect, vec2(0.0))
+    ).r;
+    float occluder_distance = linearize_depth(depth, light.near_far);
+    float frag_distance = dot(-frag_to_light, light.direction.xyz);
+
+    float shadow_bias = max(
+        light.shadow_bias_min_max.y * (1.0 - dot(normal, normalize(frag_to_light))),
+        light.shadow_bias_min_max.x
+    );
+
+    return frag_distance - shadow_bias <= occluder_distance ? 1.0 : 0.0;
+}
+
+vec2 directional_shadow_texel_size() {
+#ifdef SHADOW_COMPARISON_SAMPLER
+    ivec2 size = textureSize(sampler2DShadow(DirectionalLightTexture, DirectionalLightSampler), 0);
+#else
+    ivec2 size = textureSize(sampler2D(DirectionalLightTexture, DirectionalLightSampler), 0);
+#endif
+    return 1.0 / vec2(size.xy);
+}
+
+// Returns 1.0 if depth is in front of the occluder stored in the shadow map and 0.0 otherwise,
+// or a bilinear blend of the surrounding texels when using a comparison sampler.
+float sample_directional_shadow(vec2 uv, vec4 rect, float depth) {
+    vec2 coords = atlas_coords(uv, rect, directional_shadow_texel_size());
+#ifdef SHADOW_COMPARISON_SAMPLER
+    return texture(
+        sampler2DShadow(DirectionalLightTexture, DirectionalLightSampler),
+        vec3(coords, depth)
+    );
+#else
+    float occluder = texture(
+        sampler2D(DirectionalLightTexture, DirectionalLightSampler),
+        coords
+    ).r;
+    return depth <= occluder ? 1.0 : 0.0;
+#endif
+}
+
+#if defined(SHADOW_FILTER_POISSON) || defined(SHADOW_FILTER_PCSS)
+const vec2 POISSON_DISK[16] = vec2[](
+    vec2(-0.94201624, -0.39906216),
+    vec2(0.94558609, -0.76890725),
+    vec2(-0.09418410, -0.92938870),
+    vec2(0.34495938, 0.29387760),
+    vec2(-0.91588581, 0.45771432),
+    vec2(-0.81544232, -0.87912464),
+    vec2(-0.38277543, 0.27676845),
+    vec2(0.97484398, 0.75648379),
+    vec2(0.44323325, -0.97511554),
+    vec2(0.53742981, -0.47373420),
+    vec2(-0.26496911, -0.41893023),
+    vec2(0.79197514, 0.19090188),
+    vec2(-0.24188840, 0.99706507),
+    vec2(-0.81409955, 0.91437590),
+    vec2(0.19984126, 0.78641367),
+    vec2(0.14383161, -0.14100790)
+);
+
+// Interleaved gradient noise from
+// http://www.iryoku.com/next-generation-post-processing-in-call-of-duty-advanced-warfare
+float interleaved_gradient_noise(vec2 pixel) {
+    return fract(52.9829189 * fract(dot(pixel, vec2(0.06711056, 0.00583715))));
+}
+#endif
+
+#ifdef SHADOW_FILTER_PCSS
+// Percentage-closer soft shadows, see https://developer.download.nvidia.com/shaderlibrary/docs/shadow_PCSS.pdf
+// penumbra_scale converts a light space depth difference to a penumbra width in uv space.
+float pcss(vec2 uv, vec4 rect, float depth, float penumbra_scale, vec2 texel_size) {
+    float angle = 2.0 * SHADOW_PI * interleaved_gradient_noise(gl_FragCoord.xy);
+    float s = sin(angle);
+    float c = cos(angle);
+    mat2 rotation = mat2(c, s, -s, c);
+
+    vec2 max_radius = SHADOW_FILTER_RADIUS * texel_size;
+
+    // blocker search, the region is the part of the shadow map that can occlude the light
+    // as seen from the fragment
+    vec2 search_radius = min(vec2(depth * penumbra_scale), max_radius);
+    float blocker_depth = 0.0;
+    float blockers = 0.0;
+    for (int i = 0; i < 16; ++i) {
+        vec2 offset = rotation * POISSON_DISK[i] * search_radius;
+        float occluder = texture(
+            sampler2D(DirectionalLightTexture, DirectionalLightSampler),
+            atlas_coords(uv + offset, rect, directional_shadow_texel_size())
+        ).r;
+        if (occluder < depth) {
+            blocker_depth += occluder;
+            blockers += 1.0;
+        }
+    }
+
+    if (blockers == 0.0) {
+        return 1.0;
+    }
+
+    blocker_depth /= blockers;
+
+    // the penumbra grows linearly with the distance between receiver and blocker
+    vec2 filter_radius = clamp(vec2((depth - blocker_depth) * penumbra_scale), texel_size, max_radius);
+    float visibility = 0.0;
+    for (int i = 0; i < 16; ++i) {
+        vec2 offset = rotation * POISSON_DISK[i] * filter_radius;
+        visibility += sample_directional_shadow(uv + offset, rect, depth);
+    }
+    return visibility / 16.0;
+}
+#endif
+
+#ifdef SHADOW_MOMENTS
+#    if defined(SHADOW_MOMENTS_VSM) || defined(SHADOW_MOMENTS_EVSM)
+// Upper bound of the fraction of the distribution described by the moments that lies
+// behind depth, reduced by the light bleed reduction.
+float chebyshev_upper_bound(vec2 moments, float depth, float min_variance) {
+    if (depth <= moments.x) {
+        return 1.0;
+    }
+
+    float variance = max(moments.y - moments.x * moments.x, min_variance);
+    float d = depth - moments.x;
+    float p_max = variance / (variance + d * d);
+
+    return clamp((p_max - SHADOW_LIGHT_BLEED_REDUCTION) / (1.0 - SHADOW_LIGHT_BLEED_REDUCTION), 0.0, 1.0);
+}
+#    endif
+
+#    ifdef SHADOW_MOMENTS_EVSM
+// reflects evsm_warp in shadow_moments.frag
+vec2 evsm_warp(float depth) {
+    depth = 2.0 * depth - 1.0;
+    return vec2(
+        exp(SHADOW_EVSM_POSITIVE_EXPONENT * depth),
+        -exp(-SHADOW_EVSM_NEGATIVE_EXPONENT * depth)
+    );
+}
+#    endif
+
+#    ifdef SHADOW_MOMENTS_MSM
+#        ifdef SHADOW_MSM_QUANTIZED
+// reflects msm_quantize in shadow_moments.frag
+vec4 msm_dequantize(vec4 quantized) {
+    quantized.x -= 0.035955884801;
+    return mat4(
+        0.2227744146, 0.1549679261, 0.1451988946, 0.163127443,
+        0.0771972861, 0.1394629426, 0.2120202157, 0.2591432266,
+        0.7926986636, 0.7963415838, 0.7258694464, 0.6539092497,
+        0.0319417555, -0.1722823173, -0.2758014811, -0.3376131734
+    ) * quantized;
+}
+#        endif
+
+// Hamburger 4MSM from "Moment Shadow Mapping", Peters and Klein 2015.
+// Returns the visibility of a fragment at depth given the moments of the occluders.
+float hamburger_4msm(vec4 moments, float depth) {
+    vec4 b = mix(moments, vec4(0.5), SHADOW_MOMENT_BIAS);
+
+    // Cholesky decomposition of the Hankel matrix of the moments
+    float L32D22 = -b.x * b.y + b.z;
+    float D22 = -b.x * b.x + b.y;
+    float squared_depth_variance = -b.y * b.y + b.w;
+    float D33D22 = dot(vec2(squared_depth_variance, -L32D22), vec2(D22, L32D22));
+    float inv_D22 = 1.0 / D22;
+    float L32 = L32D22 * inv_D22;
+
+    // solve for the coefficients of the polynomial with roots at the support points
+    vec3 c = vec3(1.0, depth, depth * depth);
+    c.y -= b.x;
+    c.z -= b.y + L32 * c.y;
+    c.y *= inv_D22;
+    c.z *= D22 / D33D22;
+    c.y -= L32 * c.z;
+    c.x -= dot(c.yz, b.xy);
+
+    float p = c.y / c.z;
+    float q = c.x / c.z;
+    float r = sqrt(p * p * 0.25 - q);
+    vec3 z = vec3(depth, -p * 0.5 - r, -p * 0.5 + r);
+
+    vec4 switch_value = z.z < z.x ? vec4(z.y, z.x, 1.0, 1.0)
+        : (z.y < z.x ? vec4(z.x, z.y, 0.0, 1.0) : vec4(0.0));
+    float quotient = (switch_value.x * z.z - b.x * (switch_value.x + z.z) + b.y)
+        / ((z.z - switch_value.y) * (z.x - z.y));
+    float shadow_intensity = clamp(switch_value.z + switch_value.w * quotient, 0.0, 1.0);
+
+    float visibility = 1.0 - shadow_intensity;
+    return clamp((visibility - SHADOW_LIGHT_BLEED_REDUCTION) / (1.0 - SHADOW_LIGHT_BLEED_REDUCTION), 0.0, 1.0);
+}
+#    endif
+
+// Bilinearly filtered moments at coords of the atlas. 32 bit float moments are sampled without
+// filtering, so the four surrounding texels are blended here.
+vec4 sample_moments(vec2 coords, vec2 texel_size) {
+#    ifdef SHADOW_MOMENTS_MANUAL_FILTER
+    vec2 texel = coords / texel_size - 0.5;
+    vec2 weight = fract(texel);
+    vec2 base = (floor(texel) + 0.5) * texel_size;
+
+    vec4 m00 = texture(sampler2D(DirectionalLightMoments, DirectionalLightMomentsSampler), base);
+    vec4 m10 = texture(
+        sampler2D(DirectionalLightMoments, DirectionalLightMomentsSampler),
+        base + vec2(texel_size.x, 0.0)
+    );
+    vec4 m01 = texture(
+        sampler2D(DirectionalLightMoments, DirectionalLightMomentsSampler),
+        base + vec2(0.0, texel_size.y)
+    );
+    vec4 m11 = texture(
+        sampler2D(DirectionalLightMoments, DirectionalLightMomentsSampler),
+        base + texel_size
+    );
+
+    return mix(mix(m00, m10, weight.x), mix(m01, m11, weight.x), weight.y);
+#    else
+    return texture(sampler2D(DirectionalLightMoments, DirectionalLightMomentsSampler), coords);
+#    endif
+}
+
+float moment_shadow(vec2 uv, vec4 rect, float depth) {
+    vec2 texel_size = 1.0 / vec2(textureSize(sampler2D(DirectionalLightMoments, DirectionalLightMomentsSampler), 0).xy);
+    vec4 moments = sample_moments(atlas_coords(uv, rect, texel_size), texel_size);
+
+#    if defined(SHADOW_MOMENTS_VSM)
+    return chebyshev_upper_bound(moments.xy, depth, SHADOW_MIN_VARIANCE);
+#    elif defined(SHADOW_MOMENTS_EVSM)
+    vec2 warped = evsm_warp(depth);
+    // the variance scales with the derivative of the warp
+    vec2 derivative = vec2(SHADOW_EVSM_POSITIVE_EXPONENT, SHADOW_EVSM_NEGATIVE_EXPONENT) * warped;
+    vec2 min_variance = SHADOW_MIN_VARIANCE * derivative * derivative;
+
+    float positive = chebyshev_upper_bound(moments.xy, warped.x, min_variance.x);
+    float negative = chebyshev_upper_bound(moments.zw, warped.y, min_variance.y);
+    return min(positive, negative);
+#    elif defined(SHADOW_MOMENTS_ESM)
+    // moments.x is exp(c * occluder_depth), so this is exp(c * (occluder_depth - depth))
+    return clamp(moments.x * exp(-SHADOW_ESM_EXPONENT * depth), 0.0, 1.0);
+#    elif defined(SHADOW_MOMENTS_MSM)
+#        ifdef SHADOW_MSM_QUANTIZED
+    moments = msm_dequantize(moments);
+#        endif
+    return hamburger_4msm(moments, depth);
+#    endif
+}
+#endif
+
+// Filters the shadow map around uv according to the SHADOW_FILTER_* shader defs.
+float filter_directional_shadow(vec2 uv, vec4 rect, float depth, float penumbra_scale) {
+    // texel size in the uv space of the shadow map rather than the atlas
+    vec2 texel_size = directional_shadow_texel_size() / rect.z;
+
+#if defined(SHADOW_MOMENTS)
+    return moment_shadow(uv, rect, depth);
+#elif defined(SHADOW_FILTER_PCSS)
+    return pcss(uv, rect, depth, penumbra_scale, texel_size);
+#elif defined(SHADOW_FILTER_BOX)
+    const int half_size = SHADOW_FILTER_BOX_SIZE / 2;
+    // even sizes are centered between texels
+    const float center = (SHADOW_FILTER_BOX_SIZE % 2 == 0) ? 0.5 : 0.0;
+
+    float visibility = 0.0;
+    for (int x = 0; x < SHADOW_FILTER_BOX_SIZE; ++x) {
+        for (int y = 0; y < SHADOW_FILTER_BOX_SIZE; ++y) {
+            vec2 offset = vec2(x - half_size, y - half_size) + center;
+            visibility += sample_directional_shadow(uv + offset * texel_size, rect, depth);
+        }
+    }
+    return visibility / float(SHADOW_FILTER_BOX_SIZE * SHADOW_FILTER_BOX_SIZE);
+#elif defined(SHADOW_FILTER_POISSON)
+#    ifdef SHADOW_FILTER_ROTATED
+    float angle = 2.0 * SHADOW_PI * interleaved_gradient_noise(gl_FragCoord.xy);
+    float s = sin(angle);
+    float c = cos(angle);
+    mat2 rotation = mat2(c, s, -s, c);
+#    else
+    mat2 rotation = mat2(1.0);
+#    endif
+
+    float visibility = 0.0;
+    for (int i = 0; i < 16; ++i) {
+        vec2 offset = rotation * POISSON_DISK[i] * SHADOW_FILTER_RADIUS;
+        visibility += sample_directional_shadow(uv + offset * texel_size, rect, depth);
+    }
+    return visibility / 16.0;
+#else
+    return sample_directional_shadow(uv, rect, depth);
+#endif
+}
+
+#ifdef SHADOW_TRANSLUCENT
+// Returns the color of the light let through by translucent casters in front of the fragment.
+vec3 directional_transmittance(vec2 uv, vec4 rect, float depth) {
+    vec2 texel_size = 1.0 / vec2(textureSize(sampler2D(DirectionalLightTransmittance, DirectionalLightTransmittanceSampler), 0));
+    vec2 coords = atlas_coords(uv, rect, texel_size);
+
+    float translucent_depth = texture(
+        sampler2D(DirectionalLightTransmittanceDepth, DirectionalLightTransmittanceDepthSampler),
+        coords
+    ).r;
+    if (depth <= translucent_depth) {
+        return vec3(1.0);
+    }
+
+    return texture(
+        sampler2D(DirectionalLightTransmittance, DirectionalLightTransmittanceSampler),
+        coords
+    ).rgb;
+}
+#endif
+
+// Returns the color of the light reaching the fragment, 0.0 if shadowed and 1.0 if lit.
+vec3 directional_shadow(ShadowDirectionalLight shadow_light, vec3 world_pos, vec3 normal, vec3 light_direction) {
+#ifdef NOT_SHADOW_RECEIVER
+    return vec3(1.0);
+#endif
+    // use the first cascade that contains the fragment, cascades are ordered by distance from the camera
+    for (uint cascade = 0u; cascade < shadow_light.cascadeCount; ++cascade) {
+        vec4 p = shadow_light.viewProj[cascade] * vec4(world_pos, 1.0);
+        vec2 uv = p.xy;
+        uv.y *= -1.0;
+        uv += 1.0;
+        uv /= 2.0;
+
+        if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+            continue;
+        }
+
+        float shadow_bias = max(
+            shadow_light.shadow_bias_min_max.y * (1.0 - dot(normal, light_direction)),
+            shadow_light.shadow_bias_min_max.x
+        );
+        // Clamping the light space z to the shadow map range prevents objects further away than the far plane
+        // from always being considered as occluded
+        float depth = clamp(p.z, 0.0, 1.0 + shadow_bias) - shadow_bias;
+
+        // the view projection is an orthographic projection of a rigid transform, so the length of
+        // its rows is the scale from world units to clip space along each axis
+        mat4 view_proj = shadow_light.viewProj[cascade];
+        float uv_per_world = 0.5 * length(vec3(view_proj[0][0], view_proj[1][0], view_proj[2][0]));
+        float depth_per_world = length(vec3(view_proj[0][2], view_proj[1][2], view_proj[2][2]));
+        float penumbra_scale = shadow_light.light_size * uv_per_world / depth_per_world;
+
+        vec4 rect = shadow_light.atlasRects[cascade];
+        float visibility = filter_directional_shadow(uv, rect, depth, penumbra_scale);
+#ifdef SHADOW_TRANSLUCENT
+        return visibility * directional_transmittance(uv, rect, depth);
+#else
+        return vec3(visibility);
+#endif
+    }
+
+    return vec3(1.0);
+}
+
+// Returns the color of the light of the directional light at light_index reaching world_pos,
+// 0.0 if shadowed and 1.0 if lit. normal is the normalized world space normal of the surface.
+vec3 shadow_visibility(vec3 world_pos, vec3 normal, int light_index) {
+    if (light_index < 0 || light_index >= int(NumLights.y) || light_index >= MAX_DIRECTIONAL_LIGHTS) {
+        return vec3(1.0);
+    }
+
+    return directional_shadow(
+        shadow_directional_lights[light_index],
+        world_pos,
+        normal,
+        DirectionalLights[light_index].direction.xyz
+    );
+}
+
+#endif
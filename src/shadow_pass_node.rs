@@ -1,16 +1,36 @@
-use crate::{prelude::DIRECTIONAL_LIGHT_DEPTH_HANDLE, render_graph::SHADOW_PIPELINE};
+use crate::{
+    atlas::{AtlasRect, ShadowAtlas},
+    frustum::{Frustum, ShadowCamera},
+    prelude::{
+        DIRECTIONAL_LIGHT_DEPTH_HANDLE, DIRECTIONAL_LIGHT_MOMENTS_HANDLE,
+        DIRECTIONAL_LIGHT_TRANSMITTANCE_DEPTH_HANDLE, DIRECTIONAL_LIGHT_TRANSMITTANCE_HANDLE,
+        POINT_LIGHT_DEPTH_HANDLE, SPOT_LIGHT_DEPTH_HANDLE,
+    },
+    render_graph::{
+        SHADOW_MASKED_MOMENTS_PIPELINE, SHADOW_MASKED_PIPELINE, SHADOW_MOMENTS_PIPELINE,
+        SHADOW_PIPELINE, SHADOW_TRANSLUCENT_PIPELINE,
+    },
+};
 use bevy::{
     core::bytes_of,
     ecs::{system::BoxedSystem, world::World},
-    pbr::render_graph::MAX_DIRECTIONAL_LIGHTS,
+    pbr::render_graph::{MAX_DIRECTIONAL_LIGHTS, MAX_POINT_LIGHTS},
     prelude::*,
     prelude::{QueryState, Res},
     render::{
+        camera::{Camera, PerspectiveProjection},
         draw::{DrawContext, RenderCommand},
         mesh::{Indices, INDEX_BUFFER_ASSET_INDEX, VERTEX_ATTRIBUTE_BUFFER_ID},
-        pass::{Operations, PassDescriptor, RenderPassDepthStencilAttachment, TextureAttachment},
-        pipeline::{PipelineDescriptor, RenderPipeline},
-        render_graph::{CommandQueue, Node, ResourceSlotInfo, ResourceSlots, SystemNode},
+        pass::{
+            LoadOp, Operations, PassDescriptor, RenderPass, RenderPassColorAttachment,
+            RenderPassDepthStencilAttachment, TextureAttachment,
+        },
+        pipeline::{PipelineDescriptor, PipelineSpecialization},
+        render_graph::{
+            base::{camera::CAMERA_3D, MainPass},
+            CommandQueue, Node, ResourceSlotInfo, ResourceSlots, SystemNode,
+        },
+        renderer::RenderResources,
         renderer::{
             BufferId, BufferInfo, BufferMapMode, BufferUsage, RenderContext, RenderResourceBinding,
             RenderResourceBindings, RenderResourceContext, RenderResourceId, RenderResourceType,
@@ -22,16 +42,29 @@ use bevy::{
         },
     },
 };
+use bevy_mod_bounding::sphere;
 use bytemuck::{cast_slice, Pod, Zeroable};
 use std::borrow::Cow;
+use std::cmp::Reverse;
 use std::collections::HashMap;
 
 pub(crate) fn shadow_lights_register_system<L: Light>(
     mut shadow_lights: ResMut<ShadowLights>,
-    query: Query<Entity, (With<L>, Added<L>)>,
+    query: Query<(Entity, &L, Option<&L::Config>), Or<(Added<L>, Changed<L::Config>)>>,
 ) {
-    for entity in query.iter() {
-        shadow_lights.add(entity);
+    let mut repack = false;
+
+    for (entity, light, config) in query.iter() {
+        repack |= shadow_lights.add(
+            entity,
+            L::KIND,
+            light.shadow_resolution(config),
+            light.shadow_view_count(config, shadow_lights.max_cascades),
+        );
+    }
+
+    if repack {
+        shadow_lights.repack(L::KIND);
     }
 }
 
@@ -39,72 +72,431 @@ pub(crate) fn shadow_lights_remove_system<L: Light>(
     mut shadow_lights: ResMut<ShadowLights>,
     removed: RemovedComponents<L>,
 ) {
+    let mut repack = false;
+
     for entity in removed.iter() {
-        shadow_lights.remove(entity);
+        repack |= shadow_lights.remove(entity);
+    }
+
+    if repack {
+        shadow_lights.repack(L::KIND);
     }
 }
 
+/// Neither casts nor receives shadows, same as [`NotShadowCaster`] and [`NotShadowReceiver`].
 #[derive(Default, Clone, Copy)]
 pub struct Shadowless;
 
+/// Isn't drawn into shadow maps, but still receives shadows.
+#[derive(Default, Clone, Copy)]
+pub struct NotShadowCaster;
+
+/// Isn't shadowed when drawn with the shadow pbr pipeline, but still casts shadows.
+#[derive(Default, Clone, Copy)]
+pub struct NotShadowReceiver;
+
+/// A mesh drawn into the shadow maps instead of the entity's own mesh, e.g. a coarse hull
+/// of a detailed mesh. The entity's transform is still used.
+#[derive(Clone, Debug, Default)]
+pub struct ShadowProxy(pub Handle<Mesh>);
+
+/// Discards the parts of a caster whose base color alpha is below `cutoff` when it's drawn
+/// into shadow maps, e.g. leaves and fences. The caster needs a [`StandardMaterial`].
+#[derive(RenderResources, Clone, Copy, Debug)]
+pub struct ShadowAlphaMask {
+    pub cutoff: f32,
+}
+
+impl Default for ShadowAlphaMask {
+    fn default() -> Self {
+        Self { cutoff: 0.5 }
+    }
+}
+
+/// Tints the light of directional lights by the base color of the caster, weighted by its alpha,
+/// instead of blocking it, e.g. for stained glass. Only used if
+/// [`crate::ShadowPlugin::translucent_shadows`] is enabled, other lights ignore these casters.
+/// The caster needs a [`StandardMaterial`].
+#[derive(Default, Clone, Copy)]
+pub struct ShadowTranslucent;
+
+/// A depth-only pipeline the entity is drawn into shadow maps with instead of the default shadow
+/// pipelines, e.g. to displace vertices like the main pipeline does. `ViewProj` and `Transform` are
+/// bound as dynamic uniforms like in `shadow.vert`, and the entity's [`RenderPipelines`] bindings
+/// are available, including the [`ShadowAlphaMask`] cutoff if the pipeline discards fragments.
+///
+/// The pipeline must have no color targets and the `Depth32Float` depth stencil state of
+/// [`PipelineDescriptor::default_config`]. Directional lights render into a moment color target
+/// if the filter is moment based, their shadow maps use the default moment pipelines instead.
+#[derive(Clone, Debug, Default)]
+pub struct ShadowPipeline(pub Handle<PipelineDescriptor>);
+
+/// Casts shadows but isn't drawn in the main pass, e.g. the body of a first person player.
+#[derive(Default, Clone, Copy)]
+pub struct ShadowCasterOnly;
+
+/// Marks entities whose [`MainPass`] was removed by [`shadow_caster_only_system`].
+pub(crate) struct ShadowCasterOnlyMainPass;
+
+/// Takes shadow caster only entities out of the main pass, and puts them back once
+/// [`ShadowCasterOnly`] is removed. They stay [`Visible`], so their `Transform` and material
+/// bindings keep being updated for the shadow pass.
+pub(crate) fn shadow_caster_only_system(
+    mut commands: Commands,
+    caster_only: Query<Entity, (With<ShadowCasterOnly>, With<MainPass>)>,
+    removed: Query<Entity, (With<ShadowCasterOnlyMainPass>, Without<ShadowCasterOnly>)>,
+) {
+    for entity in caster_only.iter() {
+        commands
+            .entity(entity)
+            .remove::<MainPass>()
+            .insert(ShadowCasterOnlyMainPass);
+    }
+
+    for entity in removed.iter() {
+        commands
+            .entity(entity)
+            .remove::<ShadowCasterOnlyMainPass>()
+            .insert(MainPass);
+    }
+}
+
+/// Sets the `NOT_SHADOW_RECEIVER` shader def, which disables shadow lookups in `shadows.glsl`.
+/// Bevy clears the shader defs every frame, so it's set every frame as well.
+pub(crate) fn not_shadow_receiver_system(
+    mut query: Query<&mut RenderPipelines, Or<(With<NotShadowReceiver>, With<Shadowless>)>>,
+) {
+    for mut render_pipelines in query.iter_mut() {
+        for render_pipeline in render_pipelines.pipelines.iter_mut() {
+            render_pipeline
+                .specialization
+                .shader_specialization
+                .shader_defs
+                .insert("NOT_SHADOW_RECEIVER".to_string());
+        }
+    }
+}
+
+/// Which shadow map texture and uniform array a light uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LightKind {
+    Directional,
+    Point,
+    Spot,
+}
+
+impl Default for LightKind {
+    fn default() -> Self {
+        LightKind::Directional
+    }
+}
+
+impl LightKind {
+    /// Number of lights of this kind in the shadow lights uniform.
+    pub fn max_lights(&self) -> usize {
+        match self {
+            LightKind::Directional => MAX_DIRECTIONAL_LIGHTS,
+            LightKind::Point => MAX_POINT_LIGHTS,
+            LightKind::Spot => MAX_SPOT_LIGHTS,
+        }
+    }
+}
+
 pub trait Light: Send + Sync + 'static {
     type Config: Send + Sync + 'static;
 
+    const KIND: LightKind;
+
     fn proj_matrix(&self, config: Option<&Self::Config>) -> Mat4;
-    fn view_matrix(&self) -> Mat4;
+    fn view_matrix(&self, transform: &GlobalTransform, config: Option<&Self::Config>) -> Mat4;
     fn shadow_bias_min_max(&self, config: Option<&Self::Config>) -> Vec2;
+
+    /// Requested resolution of every shadow map of this light,
+    /// if None the resolution set in the [`crate::ShadowPlugin`] is used.
+    fn shadow_resolution(&self, _config: Option<&Self::Config>) -> Option<u32> {
+        None
+    }
+
+    /// Number of shadow maps allocated in the atlas for this light. Directional lights
+    /// allocate one per cascade, at most `max_cascades`.
+    fn shadow_view_count(&self, _config: Option<&Self::Config>, _max_cascades: usize) -> usize {
+        1
+    }
+
+    /// Size of the light source used for soft shadows. For directional lights this is the
+    /// width of the light per unit of distance to the occluder, `2 * tan(angular_diameter / 2)`.
+    fn light_size(&self, _config: Option<&Self::Config>) -> f32 {
+        0.0
+    }
+
+    /// Near and far plane of perspective projections, used to linearize the shadow map depth.
+    fn depth_range(&self, _config: Option<&Self::Config>) -> Vec2 {
+        Vec2::ZERO
+    }
+
+    /// Shading parameters for lights that bevy's lights uniform doesn't cover.
+    fn shading(&self) -> Option<LightShading> {
+        None
+    }
+
+    /// Returns the view projection of every shadow map rendered for this light,
+    /// `resolution` is the size of the shadow maps in texels. Returns at most
+    /// [`Light::shadow_view_count`] view projections for the same `max_cascades`.
+    fn view_projections(
+        &self,
+        transform: &GlobalTransform,
+        config: Option<&Self::Config>,
+        _camera: Option<&ShadowCamera>,
+        _resolution: u32,
+        _max_cascades: usize,
+    ) -> Vec<Mat4> {
+        vec![self.proj_matrix(config) * self.view_matrix(transform, config)]
+    }
+}
+
+/// Parameters needed to shade lights that are lit by `shadow_pbr.frag` itself.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LightShading {
+    /// Linear color premultiplied by the intensity of the light.
+    pub color: Vec4,
+    pub range: f32,
+    /// Cosine of the inner and outer angle of spot light cones.
+    pub cos_inner_outer: Vec2,
 }
 
+/// A single shadow map rendered for a light, e.g. one cascade of a directional light.
 #[derive(Default)]
-pub struct ShadowLight {
+pub struct ShadowView {
     staging_buffer: Option<BufferId>,
     draw: Draw,
-    texture_index: usize,
-    pos: Vec3,
+    /// Translucent casters, drawn into the transmittance atlas after the opaque ones.
+    translucent_draw: Draw,
+    /// Translucent casters, drawn into the transmittance depth atlas by the depth pipelines.
+    translucent_depth_draw: Draw,
     view_proj: Mat4,
-    shadow_bias_min_max: Vec2,
     pub bindings: RenderResourceBindings,
 }
 
+/// Number of shadow casters drawn into and culled from the shadow maps of a light
+/// in the last frame, summed over all of its shadow maps.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShadowCasterStats {
+    pub drawn: usize,
+    /// Casters outside of the frustum of a shadow map.
+    pub frustum_culled: usize,
+    /// Casters inside the frustum whose shadows can't land in the view of the camera.
+    pub volume_culled: usize,
+}
+
+#[derive(Default)]
+pub struct ShadowLight {
+    kind: LightKind,
+    /// Index of the light in the bevy lights uniform, which iterates lights in query order. `None`
+    /// until the light is assigned a slot in `LightsNode::prepare`.
+    index: Option<usize>,
+    resolution: Option<u32>,
+    view_count: usize,
+    /// Region of the shadow atlas of every view, empty if the light didn't fit into the atlas.
+    rects: Vec<AtlasRect>,
+    pos: Vec3,
+    /// Forward (-Z) axis of the light's transform.
+    direction: Vec3,
+    shading: LightShading,
+    shadow_bias_min_max: Vec2,
+    light_size: f32,
+    depth_range: Vec2,
+    views: Vec<ShadowView>,
+    caster_stats: ShadowCasterStats,
+}
+
 #[derive(Default)]
 pub struct ShadowLights {
     lights: HashMap<Entity, ShadowLight>,
+    /// One atlas per light kind, packed into the kind's shadow map texture.
+    atlases: HashMap<LightKind, ShadowAtlas>,
+    /// Number of views each directional light can allocate.
+    max_cascades: usize,
+    /// See [`crate::ShadowPlugin::cull_casters`].
+    cull_casters: bool,
 }
 
 impl ShadowLights {
-    fn add(&mut self, entity: Entity) {
-        self.lights.insert(entity, Default::default());
+    pub(crate) fn new(shadow_plugin: &crate::ShadowPlugin) -> Self {
+        let atlases = [LightKind::Directional, LightKind::Point, LightKind::Spot]
+            .iter()
+            .map(|kind| (*kind, shadow_plugin.atlas(*kind)))
+            .collect();
+
+        Self {
+            lights: Default::default(),
+            atlases,
+            max_cascades: shadow_plugin.max_cascades(),
+            cull_casters: shadow_plugin.cull_casters,
+        }
+    }
+
+    /// Adds or updates a light, returns true if the atlas needs to be repacked.
+    fn add(
+        &mut self,
+        entity: Entity,
+        kind: LightKind,
+        resolution: Option<u32>,
+        view_count: usize,
+    ) -> bool {
+        let view_count = match kind {
+            LightKind::Directional => view_count.clamp(1, self.max_cascades),
+            LightKind::Point => POINT_LIGHT_FACES,
+            LightKind::Spot => 1,
+        };
+
+        let mut added = false;
+        let light = self.lights.entry(entity).or_insert_with(|| {
+            added = true;
+
+            ShadowLight {
+                kind,
+                ..Default::default()
+            }
+        });
+
+        let changed = added || light.resolution != resolution || light.view_count != view_count;
+
+        light.resolution = resolution;
+        light.view_count = view_count;
+
+        changed
     }
 
-    fn remove(&mut self, entity: Entity) {
-        self.lights.remove(&entity);
+    /// Removes a light, returns true if it occupied space in the atlas.
+    fn remove(&mut self, entity: Entity) -> bool {
+        self.lights
+            .remove(&entity)
+            .map_or(false, |light| !light.rects.is_empty())
+    }
+
+    /// Reallocates the atlas regions of every light of a kind.
+    fn repack(&mut self, kind: LightKind) {
+        let atlas = if let Some(atlas) = self.atlases.get_mut(&kind) {
+            atlas
+        } else {
+            return;
+        };
+
+        atlas.clear();
+
+        let page_size = atlas.page_size();
+        let mut lights = self
+            .lights
+            .iter_mut()
+            .filter(|(_, light)| light.kind == kind)
+            .collect::<Vec<_>>();
+
+        // place the largest shadow maps first so the smaller ones fill the remaining gaps
+        lights.sort_by_key(|(entity, light)| {
+            (
+                Reverse(atlas.rounded_size(light.resolution.unwrap_or(page_size))),
+                entity.id(),
+            )
+        });
+
+        for (_, light) in lights {
+            let resolution = light.resolution.unwrap_or(page_size);
+
+            light.rects = atlas
+                .allocate_all(&vec![resolution; light.view_count])
+                .unwrap_or_default();
+        }
+    }
+
+    /// Returns the shadow caster culling statistics of a light, None if it doesn't cast shadows.
+    pub fn caster_stats(&self, entity: Entity) -> Option<ShadowCasterStats> {
+        self.lights.get(&entity).map(|light| light.caster_stats)
+    }
+
+    pub(crate) fn atlas_size(&self, kind: LightKind) -> u32 {
+        self.atlases
+            .get(&kind)
+            .map_or(1, |atlas| atlas.texture_size())
+    }
+
+    /// Atlas regions of every view rendered this frame for lights of a kind.
+    pub(crate) fn rendered_rects(&self, kind: LightKind) -> impl Iterator<Item = AtlasRect> + '_ {
+        self.lights
+            .values()
+            .filter(move |light| light.kind == kind)
+            .flat_map(|light| light.rects.iter().take(light.views.len()).copied())
     }
 }
 
 #[repr(C)]
 #[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
 pub struct DirectionalLightUniform {
-    pub texture_index: [u32; 4],
+    pub cascade_count: u32,
+    _padding0: [u32; 3],
     pub pos: [f32; 4],
     pub shadow_bias_min_max: [f32; 2],
+    pub light_size: f32,
+    _padding: f32,
+    /// Uv offset and uv scale of every cascade in the atlas, w is unused.
+    pub atlas_rects: [[f32; 4]; MAX_CASCADES],
+    pub view_proj: [[[f32; 4]; 4]; MAX_CASCADES],
+}
+
+/// Max number of cascades per directional light, reflected in `shadows.glsl`.
+pub const MAX_CASCADES: usize = 4;
+
+/// Vertex attributes of skinned meshes. If a mesh has both and its [`RenderPipelines`] bindings
+/// contain [`JOINT_TRANSFORMS`], it's deformed by the joint matrices in the shadow maps.
+pub(crate) const ATTRIBUTE_JOINT_WEIGHT: &str = "Vertex_JointWeight";
+pub(crate) const ATTRIBUTE_JOINT_INDEX: &str = "Vertex_JointIndex";
+/// Storage buffer of the world space joint matrices of a skinned mesh. Neither this crate nor
+/// bevy provide it, the app has to set it in the entity's [`RenderPipelines`] bindings.
+pub const JOINT_TRANSFORMS: &str = "JointTransforms";
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
+pub struct PointLightUniform {
+    /// Zero if the light has no shadow map.
+    pub face_count: u32,
+    _padding0: u32,
+    pub near_far: [f32; 2],
+    pub shadow_bias_min_max: [f32; 2],
     _padding: [f32; 2],
+    /// Uv offset and uv scale of every face in the atlas, w is unused.
+    pub atlas_rects: [[f32; 4]; POINT_LIGHT_FACES],
+    pub view_proj: [[[f32; 4]; 4]; POINT_LIGHT_FACES],
+}
+
+/// Point lights are rendered as a cube map, one layer per face.
+pub const POINT_LIGHT_FACES: usize = 6;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
+pub struct SpotLightUniform {
+    /// w is the inverse of the range squared.
+    pub pos: [f32; 4],
+    pub direction: [f32; 4],
+    pub color: [f32; 4],
+    pub cos_inner_outer: [f32; 2],
+    pub shadow_bias_min_max: [f32; 2],
+    /// Uv offset and uv scale of the shadow map in the atlas, w is unused.
+    pub atlas_rect: [f32; 4],
+    /// Zero if the light has no shadow map.
+    pub has_shadow: u32,
+    _padding: u32,
+    pub near_far: [f32; 2],
     pub view_proj: [[f32; 4]; 4],
 }
 
+/// Max number of spot lights, reflected in `shadows.glsl`.
+pub const MAX_SPOT_LIGHTS: usize = 10;
+
 #[derive(Default)]
 pub struct ShadowLightsBindNode {
     command_queue: CommandQueue,
 }
 
 impl Node for ShadowLightsBindNode {
-    fn prepare(&mut self, world: &mut World) {
-        let mut lights = world.get_resource_mut::<ShadowLights>().unwrap();
-
-        for (i, light) in lights.lights.values_mut().enumerate() {
-            light.texture_index = i;
-        }
-    }
-
     fn update(
         &mut self,
         _world: &World,
@@ -143,25 +535,112 @@ fn shadow_lights_bind_system(
     render_resource_context: Res<Box<dyn RenderResourceContext>>,
 ) {
     let directional_size = std::mem::size_of::<DirectionalLightUniform>() * MAX_DIRECTIONAL_LIGHTS;
+    let point_size = std::mem::size_of::<PointLightUniform>() * MAX_POINT_LIGHTS;
+    let spot_count_size = std::mem::size_of::<[u32; 4]>();
+    let spot_size = std::mem::size_of::<SpotLightUniform>() * MAX_SPOT_LIGHTS;
 
-    let buffer_size = directional_size;
+    let buffer_size = directional_size + point_size + spot_count_size + spot_size;
 
-    let mut directional_lights = Vec::new();
+    // lights without a shadow map are left zeroed, which the shader treats as fully lit
+    let mut directional_lights = vec![DirectionalLightUniform::default(); MAX_DIRECTIONAL_LIGHTS];
+    let mut point_lights = vec![PointLightUniform::default(); MAX_POINT_LIGHTS];
+    let mut spot_lights = vec![SpotLightUniform::default(); MAX_SPOT_LIGHTS];
+    let mut spot_light_count = 0;
 
     for light in lights.lights.values() {
-        let directional_light = DirectionalLightUniform {
-            texture_index: [light.texture_index as u32; 4],
-            pos: light.pos.extend(0.0).into(),
-            shadow_bias_min_max: light.shadow_bias_min_max.into(),
-            view_proj: light.view_proj.to_cols_array_2d(),
-            ..Default::default()
+        // lights registered this frame aren't prepared yet and would overwrite the first slot
+        let index = if let Some(index) = light.index {
+            index
+        } else {
+            continue;
         };
+        let atlas_size = lights.atlas_size(light.kind);
+        let atlas_rects = light
+            .rects
+            .iter()
+            .map(|rect| rect.uv_rect(atlas_size))
+            .collect::<Vec<_>>();
+
+        match light.kind {
+            LightKind::Directional => {
+                let directional_light = if let Some(uniform) = directional_lights.get_mut(index) {
+                    uniform
+                } else {
+                    continue;
+                };
+
+                *directional_light = DirectionalLightUniform {
+                    cascade_count: light.views.len() as u32,
+                    pos: light.pos.extend(0.0).into(),
+                    shadow_bias_min_max: light.shadow_bias_min_max.into(),
+                    light_size: light.light_size,
+                    ..Default::default()
+                };
+
+                for (view_proj, view) in directional_light.view_proj.iter_mut().zip(&light.views) {
+                    *view_proj = view.view_proj.to_cols_array_2d();
+                }
+
+                for (uniform_rect, rect) in
+                    directional_light.atlas_rects.iter_mut().zip(&atlas_rects)
+                {
+                    *uniform_rect = *rect;
+                }
+            }
+            LightKind::Point => {
+                let point_light = if let Some(uniform) = point_lights.get_mut(index) {
+                    uniform
+                } else {
+                    continue;
+                };
+
+                *point_light = PointLightUniform {
+                    face_count: light.views.len() as u32,
+                    near_far: light.depth_range.into(),
+                    shadow_bias_min_max: light.shadow_bias_min_max.into(),
+                    ..Default::default()
+                };
 
-        directional_lights.push(directional_light);
+                for (view_proj, view) in point_light.view_proj.iter_mut().zip(&light.views) {
+                    *view_proj = view.view_proj.to_cols_array_2d();
+                }
+
+                for (uniform_rect, rect) in point_light.atlas_rects.iter_mut().zip(&atlas_rects) {
+                    *uniform_rect = *rect;
+                }
+            }
+            LightKind::Spot => {
+                let spot_light = if let Some(uniform) = spot_lights.get_mut(index) {
+                    uniform
+                } else {
+                    continue;
+                };
+
+                let inverse_range_squared = 1.0 / (light.shading.range * light.shading.range);
+
+                *spot_light = SpotLightUniform {
+                    pos: light.pos.extend(inverse_range_squared).into(),
+                    direction: light.direction.extend(0.0).into(),
+                    color: light.shading.color.into(),
+                    cos_inner_outer: light.shading.cos_inner_outer.into(),
+                    shadow_bias_min_max: light.shadow_bias_min_max.into(),
+                    atlas_rect: atlas_rects.first().copied().unwrap_or_default(),
+                    has_shadow: light.views.len() as u32,
+                    near_far: light.depth_range.into(),
+                    view_proj: light
+                        .views
+                        .first()
+                        .map_or(Mat4::IDENTITY, |view| view.view_proj)
+                        .to_cols_array_2d(),
+                    ..Default::default()
+                };
+
+                spot_light_count = spot_light_count.max(index as u32 + 1);
+            }
+        }
     }
 
-    let directional_size =
-        std::mem::size_of::<DirectionalLightUniform>() * directional_lights.len();
+    let spot_offset = directional_size + point_size + spot_count_size;
 
     let staging_buffer = if let Some(staging_buffer) = state.staging_buffer {
         render_resource_context.map_buffer(staging_buffer, BufferMapMode::Write);
@@ -191,27 +670,32 @@ fn shadow_lights_bind_system(
         light_buffer
     };
 
-    if directional_lights.len() > 0 {
-        render_resource_context.write_mapped_buffer(
-            staging_buffer,
-            0..directional_size as u64,
-            &mut |data, _| {
-                data.copy_from_slice(cast_slice(&directional_lights));
-            },
-        );
-    }
+    render_resource_context.write_mapped_buffer(
+        staging_buffer,
+        0..buffer_size as u64,
+        &mut |data, _| {
+            data[..directional_size].copy_from_slice(cast_slice(&directional_lights));
+            data[directional_size..directional_size + point_size]
+                .copy_from_slice(cast_slice(&point_lights));
+            data[directional_size + point_size..spot_offset].copy_from_slice(cast_slice(&[
+                spot_light_count,
+                0,
+                0,
+                0,
+            ]));
+            data[spot_offset..].copy_from_slice(cast_slice(&spot_lights));
+        },
+    );
 
     render_resource_context.unmap_buffer(staging_buffer);
 
-    if directional_lights.len() > 0 {
-        state.command_queue.copy_buffer_to_buffer(
-            staging_buffer,
-            0,
-            light_buffer,
-            0,
-            directional_size as u64,
-        );
-    }
+    state.command_queue.copy_buffer_to_buffer(
+        staging_buffer,
+        0,
+        light_buffer,
+        0,
+        buffer_size as u64,
+    );
 
     render_resource_bindings.set(
         "ShadowLights",
@@ -226,11 +710,19 @@ fn shadow_lights_bind_system(
 pub struct LightsNode<L: Light> {
     query_state: Option<
         QueryState<(
+            Entity,
             &'static L,
             &'static GlobalTransform,
             Option<&'static L::Config>,
         )>,
     >,
+    camera_query_state: Option<
+        QueryState<(
+            &'static Camera,
+            &'static GlobalTransform,
+            &'static PerspectiveProjection,
+        )>,
+    >,
     command_queue: CommandQueue,
 }
 
@@ -238,6 +730,7 @@ impl<L: Light> Default for LightsNode<L> {
     fn default() -> Self {
         Self {
             query_state: Default::default(),
+            camera_query_state: Default::default(),
             command_queue: Default::default(),
         }
     }
@@ -245,87 +738,65 @@ impl<L: Light> Default for LightsNode<L> {
 
 impl<L: Light> Node for LightsNode<L> {
     fn prepare(&mut self, world: &mut World) {
-        const MATRIX_SIZE: usize = std::mem::size_of::<Mat4>();
-
         let query_state = self.query_state.get_or_insert_with(|| world.query());
+        let camera_query_state = self.camera_query_state.get_or_insert_with(|| world.query());
+
+        let camera = camera_query_state
+            .iter(world)
+            .find(|(camera, _, _)| camera.name.as_deref() == Some(CAMERA_3D))
+            .map(|(_, transform, projection)| ShadowCamera::new(transform, projection));
 
         let command_queue = &mut self.command_queue;
 
         world.resource_scope(
             |world, render_resource_context: Mut<Box<dyn RenderResourceContext>>| {
                 world.resource_scope(|world, mut lights: Mut<ShadowLights>| {
-                    for (entity, shadow_light) in &mut lights.lights {
-                        if let Ok((light, global_transform, config)) =
-                            query_state.get(world, *entity)
-                        {
-                            let proj = light.proj_matrix(config);
-                            let view = light.view_matrix();
-                            let view_proj = proj * view;
-
-                            shadow_light.pos = global_transform.translation;
-                            shadow_light.view_proj = view_proj;
-                            shadow_light.shadow_bias_min_max = light.shadow_bias_min_max(config);
-
-                            let staging_buffer =
-                                if let Some(staging_buffer) = shadow_light.staging_buffer {
-                                    render_resource_context
-                                        .map_buffer(staging_buffer, BufferMapMode::Write);
-                                    staging_buffer
-                                } else {
-                                    let staging_buffer =
-                                        render_resource_context.create_buffer(BufferInfo {
-                                            size: MATRIX_SIZE,
-                                            buffer_usage: BufferUsage::COPY_SRC
-                                                | BufferUsage::MAP_WRITE,
-                                            mapped_at_creation: true,
-                                        });
-
-                                    shadow_light.staging_buffer = Some(staging_buffer);
-                                    staging_buffer
-                                };
-
-                            let buffer =
-                                if let Some(RenderResourceBinding::Buffer { buffer, .. }) =
-                                    shadow_light.bindings.get("ViewProj")
-                                {
-                                    *buffer
-                                } else {
-                                    let buffer =
-                                        render_resource_context.create_buffer(BufferInfo {
-                                            size: MATRIX_SIZE,
-                                            buffer_usage: BufferUsage::COPY_DST
-                                                | BufferUsage::UNIFORM,
-                                            mapped_at_creation: false,
-                                        });
-
-                                    shadow_light.bindings.set(
-                                        "ViewProj",
-                                        RenderResourceBinding::Buffer {
-                                            buffer,
-                                            range: 0..MATRIX_SIZE as u64,
-                                            dynamic_index: Some(0),
-                                        },
-                                    );
-
-                                    buffer
-                                };
-
-                            render_resource_context.write_mapped_buffer(
-                                staging_buffer,
-                                0..MATRIX_SIZE as u64,
-                                &mut |bytes, _| {
-                                    bytes.copy_from_slice(bytes_of(&view_proj));
-                                },
-                            );
-
-                            render_resource_context.unmap_buffer(staging_buffer);
-
-                            command_queue.copy_buffer_to_buffer(
-                                staging_buffer,
-                                0,
-                                buffer,
-                                0,
-                                MATRIX_SIZE as u64,
+                    // bevy fills its lights uniform in query order, so the shadow lights are indexed the same way
+                    for (index, (entity, light, global_transform, config)) in
+                        query_state.iter(world).enumerate()
+                    {
+                        let shadow_light =
+                            if let Some(shadow_light) = lights.lights.get_mut(&entity) {
+                                shadow_light
+                            } else {
+                                continue;
+                            };
+
+                        // lights without a region in the atlas or a slot in the uniform get no shadow maps
+                        let mut view_projs = if index < L::KIND.max_lights() {
+                            let resolution = shadow_light.rects.first().map_or(1, |rect| rect.size);
+
+                            light.view_projections(
+                                global_transform,
+                                config,
+                                camera.as_ref(),
+                                resolution,
+                                lights.max_cascades,
+                            )
+                        } else {
+                            Vec::new()
+                        };
+                        view_projs.truncate(shadow_light.rects.len());
+
+                        shadow_light.index = Some(index);
+                        shadow_light.pos = global_transform.translation;
+                        shadow_light.direction = global_transform.rotation * -Vec3::Z;
+                        shadow_light.shading = light.shading().unwrap_or_default();
+                        shadow_light.shadow_bias_min_max = light.shadow_bias_min_max(config);
+                        shadow_light.light_size = light.light_size(config);
+                        shadow_light.depth_range = light.depth_range(config);
+                        shadow_light
+                            .views
+                            .resize_with(view_projs.len(), Default::default);
+
+                        for (view, view_proj) in shadow_light.views.iter_mut().zip(view_projs) {
+                            view.view_proj = view_proj;
+
+                            write_view_proj(
+                                &**render_resource_context,
+                                command_queue,
+                                view,
+                                view_proj,
                             );
                         }
                     }
@@ -345,54 +816,316 @@ impl<L: Light> Node for LightsNode<L> {
     }
 }
 
+fn write_view_proj(
+    render_resource_context: &dyn RenderResourceContext,
+    command_queue: &mut CommandQueue,
+    view: &mut ShadowView,
+    view_proj: Mat4,
+) {
+    const MATRIX_SIZE: usize = std::mem::size_of::<Mat4>();
+
+    let staging_buffer = if let Some(staging_buffer) = view.staging_buffer {
+        render_resource_context.map_buffer(staging_buffer, BufferMapMode::Write);
+        staging_buffer
+    } else {
+        let staging_buffer = render_resource_context.create_buffer(BufferInfo {
+            size: MATRIX_SIZE,
+            buffer_usage: BufferUsage::COPY_SRC | BufferUsage::MAP_WRITE,
+            mapped_at_creation: true,
+        });
+
+        view.staging_buffer = Some(staging_buffer);
+        staging_buffer
+    };
+
+    let buffer =
+        if let Some(RenderResourceBinding::Buffer { buffer, .. }) = view.bindings.get("ViewProj") {
+            *buffer
+        } else {
+            let buffer = render_resource_context.create_buffer(BufferInfo {
+                size: MATRIX_SIZE,
+                buffer_usage: BufferUsage::COPY_DST | BufferUsage::UNIFORM,
+                mapped_at_creation: false,
+            });
+
+            view.bindings.set(
+                "ViewProj",
+                RenderResourceBinding::Buffer {
+                    buffer,
+                    range: 0..MATRIX_SIZE as u64,
+                    dynamic_index: Some(0),
+                },
+            );
+
+            buffer
+        };
+
+    render_resource_context.write_mapped_buffer(
+        staging_buffer,
+        0..MATRIX_SIZE as u64,
+        &mut |bytes, _| {
+            bytes.copy_from_slice(bytes_of(&view_proj));
+        },
+    );
+
+    render_resource_context.unmap_buffer(staging_buffer);
+
+    command_queue.copy_buffer_to_buffer(staging_buffer, 0, buffer, 0, MATRIX_SIZE as u64);
+}
+
+/// Returns the render target of `size` texels per side, creating it if needed.
+pub(crate) fn scratch_texture(
+    textures: &mut HashMap<u32, TextureId>,
+    render_resource_context: &dyn RenderResourceContext,
+    size: u32,
+    format: TextureFormat,
+    usage: TextureUsage,
+) -> TextureId {
+    *textures.entry(size).or_insert_with(|| {
+        render_resource_context.create_texture(TextureDescriptor {
+            size: Extent3d::new(size, size, 1),
+            sample_count: 1,
+            mip_level_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage,
+        })
+    })
+}
+
+/// Restricts the following draws to the atlas region of a view. Primitives are clipped to the
+/// viewport, so views don't draw into each other's regions.
+pub(crate) fn set_viewport(render_pass: &mut dyn RenderPass, rect: &AtlasRect) {
+    render_pass.set_viewport(
+        rect.x as f32,
+        rect.y as f32,
+        rect.size as f32,
+        rect.size as f32,
+        0.0,
+        1.0,
+    );
+}
+
+/// Replays the render commands recorded into `draw` by a system.
+pub(crate) fn replay_render_commands(
+    render_pass: &mut dyn RenderPass,
+    draw: &Draw,
+    pipelines: &Assets<PipelineDescriptor>,
+) {
+    let mut current_pipeline = None;
+
+    for render_command in &draw.render_commands {
+        match render_command {
+            RenderCommand::SetPipeline { pipeline } => {
+                render_pass.set_pipeline(pipeline);
+                current_pipeline = Some(pipeline);
+            }
+            RenderCommand::SetBindGroup {
+                index,
+                bind_group,
+                dynamic_uniform_indices,
+            } => {
+                let pipeline = pipelines.get(current_pipeline.unwrap()).unwrap();
+                let layout = pipeline.get_layout().unwrap();
+                let bind_group_descriptor = layout.get_bind_group(*index).unwrap();
+
+                render_pass.set_bind_group(
+                    *index,
+                    bind_group_descriptor.id,
+                    *bind_group,
+                    dynamic_uniform_indices.as_deref(),
+                );
+            }
+            RenderCommand::SetVertexBuffer {
+                slot,
+                buffer,
+                offset,
+            } => {
+                render_pass.set_vertex_buffer(*slot, *buffer, *offset);
+            }
+            RenderCommand::SetIndexBuffer {
+                buffer,
+                offset,
+                index_format,
+            } => {
+                render_pass.set_index_buffer(*buffer, *offset, *index_format);
+            }
+            RenderCommand::DrawIndexed {
+                base_vertex,
+                indices,
+                instances,
+            } => {
+                render_pass.draw_indexed(indices.clone(), *base_vertex, instances.clone());
+            }
+            RenderCommand::Draw {
+                vertices,
+                instances,
+            } => {
+                render_pass.draw(vertices.clone(), instances.clone());
+            }
+        }
+    }
+}
+
+/// Renders the views of every light directly into the atlas texture of its kind,
+/// with a viewport per view. Each atlas is cleared once per frame.
 pub struct ShadowPassNode {
-    num_textures: u32,
-    textures: Vec<TextureId>,
-    extent: Extent3d,
+    /// Clear color of the moment atlas, which directional lights render their moments into
+    /// as well when a moment based filter is used.
+    moments: Option<Color>,
+    /// Depth buffer of the moment atlas, the directional light depth atlas is only
+    /// a placeholder with moment based filters.
+    moment_depth: HashMap<u32, TextureId>,
+    /// Directional lights render the transmittance of translucent casters into
+    /// the transmittance atlas, if translucent shadows are enabled.
+    translucent: bool,
+    inputs: Vec<ResourceSlotInfo>,
 }
 
 impl ShadowPassNode {
     pub const TEXTURE: &'static str = "texture";
+    pub const POINT_LIGHT_TEXTURE: &'static str = "point_light_texture";
+    pub const SPOT_LIGHT_TEXTURE: &'static str = "spot_light_texture";
+    /// Only present if the filter is moment based.
+    pub const MOMENT_TEXTURE: &'static str = "moment_texture";
+    /// Only present if translucent shadows are enabled.
+    pub const TRANSMITTANCE_TEXTURE: &'static str = "transmittance_texture";
+    /// Only present if translucent shadows are enabled.
+    pub const TRANSMITTANCE_DEPTH_TEXTURE: &'static str = "transmittance_depth_texture";
+
+    pub(crate) fn new(shadow_plugin: &crate::ShadowPlugin) -> Self {
+        let moments = shadow_plugin
+            .filter
+            .moment_format()
+            .map(|_| shadow_plugin.filter.moment_clear_color());
+
+        let mut inputs = vec![
+            ResourceSlotInfo {
+                name: Cow::Borrowed(Self::TEXTURE),
+                resource_type: RenderResourceType::Texture,
+            },
+            ResourceSlotInfo {
+                name: Cow::Borrowed(Self::POINT_LIGHT_TEXTURE),
+                resource_type: RenderResourceType::Texture,
+            },
+            ResourceSlotInfo {
+                name: Cow::Borrowed(Self::SPOT_LIGHT_TEXTURE),
+                resource_type: RenderResourceType::Texture,
+            },
+        ];
+
+        if moments.is_some() {
+            inputs.push(ResourceSlotInfo {
+                name: Cow::Borrowed(Self::MOMENT_TEXTURE),
+                resource_type: RenderResourceType::Texture,
+            });
+        }
+
+        if shadow_plugin.translucent_shadows {
+            inputs.push(ResourceSlotInfo {
+                name: Cow::Borrowed(Self::TRANSMITTANCE_TEXTURE),
+                resource_type: RenderResourceType::Texture,
+            });
+            inputs.push(ResourceSlotInfo {
+                name: Cow::Borrowed(Self::TRANSMITTANCE_DEPTH_TEXTURE),
+                resource_type: RenderResourceType::Texture,
+            });
+        }
 
-    pub fn new(textures: u32, size: u32) -> Self {
         Self {
-            num_textures: textures,
-            textures: Vec::with_capacity(textures as usize),
-            extent: Extent3d::new(size, size, 1),
+            moments,
+            moment_depth: Default::default(),
+            translucent: shadow_plugin.translucent_shadows,
+            inputs,
+        }
+    }
+
+    fn slot(kind: LightKind) -> &'static str {
+        match kind {
+            LightKind::Directional => Self::TEXTURE,
+            LightKind::Point => Self::POINT_LIGHT_TEXTURE,
+            LightKind::Spot => Self::SPOT_LIGHT_TEXTURE,
         }
     }
 }
 
+fn bind_shadow_texture(
+    render_resource_context: &dyn RenderResourceContext,
+    bindings: &mut RenderResourceBindings,
+    handle: HandleUntyped,
+    texture_name: &str,
+    sampler_name: &str,
+) {
+    let texture = render_resource_context
+        .get_asset_resource_untyped(handle.clone_weak(), TEXTURE_ASSET_INDEX);
+    let sampler = render_resource_context.get_asset_resource_untyped(handle, SAMPLER_ASSET_INDEX);
+
+    if let Some(texture) = texture {
+        bindings.set(
+            texture_name,
+            RenderResourceBinding::Texture(texture.get_texture().unwrap()),
+        );
+        bindings.set(
+            sampler_name,
+            RenderResourceBinding::Sampler(sampler.unwrap().get_sampler().unwrap()),
+        );
+    }
+}
+
 impl Node for ShadowPassNode {
     fn input(&self) -> &[ResourceSlotInfo] {
-        &[ResourceSlotInfo {
-            name: Cow::Borrowed(Self::TEXTURE),
-            resource_type: RenderResourceType::Texture,
-        }]
+        &self.inputs
     }
 
     fn prepare(&mut self, world: &mut World) {
-        let render_resource_context = world
-            .get_resource::<Box<dyn RenderResourceContext>>()
-            .unwrap();
-
-        let texture = render_resource_context
-            .get_asset_resource_untyped(DIRECTIONAL_LIGHT_DEPTH_HANDLE, TEXTURE_ASSET_INDEX);
-        let sampler = render_resource_context
-            .get_asset_resource_untyped(DIRECTIONAL_LIGHT_DEPTH_HANDLE, SAMPLER_ASSET_INDEX);
-
-        let mut bindings = world.get_resource_mut::<RenderResourceBindings>().unwrap();
-
-        if let Some(texture) = texture {
-            bindings.set(
-                "DirectionalLightTexture",
-                RenderResourceBinding::Texture(texture.get_texture().unwrap()),
-            );
-            bindings.set(
-                "DirectionalLightSampler",
-                RenderResourceBinding::Sampler(sampler.unwrap().get_sampler().unwrap()),
-            );
-        }
+        world.resource_scope(
+            |world, render_resource_context: Mut<Box<dyn RenderResourceContext>>| {
+                let mut bindings = world.get_resource_mut::<RenderResourceBindings>().unwrap();
+
+                bind_shadow_texture(
+                    &**render_resource_context,
+                    &mut bindings,
+                    DIRECTIONAL_LIGHT_DEPTH_HANDLE,
+                    "DirectionalLightTexture",
+                    "DirectionalLightSampler",
+                );
+                bind_shadow_texture(
+                    &**render_resource_context,
+                    &mut bindings,
+                    POINT_LIGHT_DEPTH_HANDLE,
+                    "PointLightTexture",
+                    "PointLightSampler",
+                );
+                bind_shadow_texture(
+                    &**render_resource_context,
+                    &mut bindings,
+                    SPOT_LIGHT_DEPTH_HANDLE,
+                    "SpotLightTexture",
+                    "SpotLightSampler",
+                );
+                bind_shadow_texture(
+                    &**render_resource_context,
+                    &mut bindings,
+                    DIRECTIONAL_LIGHT_MOMENTS_HANDLE,
+                    "DirectionalLightMoments",
+                    "DirectionalLightMomentsSampler",
+                );
+                bind_shadow_texture(
+                    &**render_resource_context,
+                    &mut bindings,
+                    DIRECTIONAL_LIGHT_TRANSMITTANCE_HANDLE,
+                    "DirectionalLightTransmittance",
+                    "DirectionalLightTransmittanceSampler",
+                );
+                bind_shadow_texture(
+                    &**render_resource_context,
+                    &mut bindings,
+                    DIRECTIONAL_LIGHT_TRANSMITTANCE_DEPTH_HANDLE,
+                    "DirectionalLightTransmittanceDepth",
+                    "DirectionalLightTransmittanceDepthSampler",
+                );
+            },
+        );
     }
 
     fn update(
@@ -402,37 +1135,63 @@ impl Node for ShadowPassNode {
         input: &ResourceSlots,
         _output: &mut ResourceSlots,
     ) {
-        if self.textures.len() < self.num_textures as usize {
-            let render_resources_context = render_context.resources_mut();
-            let n = self.num_textures as usize - self.textures.len();
-
-            for _ in 0..n {
-                let texture = render_resources_context.create_texture(TextureDescriptor {
-                    size: self.extent.clone(),
-                    sample_count: 1,
-                    mip_level_count: 1,
-                    dimension: TextureDimension::D2,
-                    format: TextureFormat::Depth32Float,
-                    usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::COPY_SRC,
-                });
-
-                self.textures.push(texture);
-            }
-        }
-
         let lights = world.get_resource::<ShadowLights>().unwrap();
         let render_resource_bindings = world.get_resource::<RenderResourceBindings>().unwrap();
         let pipelines = world.get_resource::<Assets<PipelineDescriptor>>().unwrap();
 
-        for shadow_light in lights.lights.values() {
-            let texture = self.textures[shadow_light.texture_index];
+        for kind in [LightKind::Directional, LightKind::Point, LightKind::Spot].iter() {
+            let shadow_lights = lights
+                .lights
+                .values()
+                .filter(|shadow_light| shadow_light.kind == *kind && !shadow_light.views.is_empty())
+                .collect::<Vec<_>>();
+
+            if shadow_lights.is_empty() {
+                continue;
+            }
+
+            let directional = *kind == LightKind::Directional;
+
+            let depth_texture = if directional && self.moments.is_some() {
+                scratch_texture(
+                    &mut self.moment_depth,
+                    render_context.resources(),
+                    lights.atlas_size(*kind),
+                    TextureFormat::Depth32Float,
+                    TextureUsage::OUTPUT_ATTACHMENT,
+                )
+            } else if let Some(RenderResourceId::Texture(depth_texture)) =
+                input.get(Self::slot(*kind))
+            {
+                depth_texture
+            } else {
+                continue;
+            };
+
+            let color_attachments = self
+                .moments
+                .filter(|_| directional)
+                .zip(input.get(Self::MOMENT_TEXTURE))
+                .and_then(|(clear_color, texture)| match texture {
+                    RenderResourceId::Texture(texture) => Some(RenderPassColorAttachment {
+                        attachment: TextureAttachment::Id(texture),
+                        resolve_target: None,
+                        ops: Operations {
+                            load: LoadOp::Clear(clear_color),
+                            store: true,
+                        },
+                    }),
+                    _ => None,
+                })
+                .into_iter()
+                .collect();
 
             let desc = PassDescriptor {
-                color_attachments: vec![],
+                color_attachments,
                 depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
-                    attachment: TextureAttachment::Id(texture),
+                    attachment: TextureAttachment::Id(depth_texture),
                     depth_ops: Some(Operations {
-                        load: bevy::render::pass::LoadOp::Clear(1.0),
+                        load: LoadOp::Clear(1.0),
                         store: true,
                     }),
                     stencil_ops: None,
@@ -440,178 +1199,349 @@ impl Node for ShadowPassNode {
                 sample_count: 1,
             };
 
-            let mut bindings = RenderResourceBindings::default();
+            render_context.begin_pass(&desc, render_resource_bindings, &mut |render_pass| {
+                for shadow_light in &shadow_lights {
+                    for (view, rect) in shadow_light.views.iter().zip(&shadow_light.rects) {
+                        set_viewport(render_pass, rect);
+                        replay_render_commands(render_pass, &view.draw, pipelines);
+                    }
+                }
+            });
 
-            bindings.extend(render_resource_bindings);
-            bindings.extend(&shadow_light.bindings);
+            let (transmittance_texture, transmittance_depth_texture) = match (
+                input.get(Self::TRANSMITTANCE_TEXTURE),
+                input.get(Self::TRANSMITTANCE_DEPTH_TEXTURE),
+            ) {
+                (
+                    Some(RenderResourceId::Texture(texture)),
+                    Some(RenderResourceId::Texture(depth_texture)),
+                ) if directional => (texture, depth_texture),
+                _ => continue,
+            };
 
-            render_context.begin_pass(&desc, &bindings, &mut |render_pass| {
-                let mut current_pipeline = None;
+            // white lets all light through, and a depth of 1.0 is behind every receiver
+            let desc = PassDescriptor {
+                color_attachments: vec![RenderPassColorAttachment {
+                    attachment: TextureAttachment::Id(transmittance_texture),
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::WHITE),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    attachment: TextureAttachment::Id(depth_texture),
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+                sample_count: 1,
+            };
 
-                for render_command in &shadow_light.draw.render_commands {
-                    match render_command {
-                        RenderCommand::SetPipeline { pipeline } => {
-                            render_pass.set_pipeline(pipeline);
-                            current_pipeline = Some(pipeline);
-                        }
-                        RenderCommand::SetBindGroup {
-                            index,
-                            bind_group,
-                            dynamic_uniform_indices,
-                        } => {
-                            let pipeline = pipelines.get(current_pipeline.unwrap()).unwrap();
-                            let layout = pipeline.get_layout().unwrap();
-                            let bind_group_descriptor = layout.get_bind_group(*index).unwrap();
-
-                            render_pass.set_bind_group(
-                                *index,
-                                bind_group_descriptor.id,
-                                *bind_group,
-                                dynamic_uniform_indices.as_deref(),
-                            );
-                        }
-                        RenderCommand::SetVertexBuffer {
-                            slot,
-                            buffer,
-                            offset,
-                        } => {
-                            render_pass.set_vertex_buffer(*slot, *buffer, *offset);
-                        }
-                        RenderCommand::SetIndexBuffer {
-                            buffer,
-                            offset,
-                            index_format,
-                        } => {
-                            render_pass.set_index_buffer(*buffer, *offset, *index_format);
-                        }
-                        RenderCommand::DrawIndexed {
-                            base_vertex,
-                            indices,
-                            instances,
-                        } => {
-                            render_pass.draw_indexed(
-                                indices.clone(),
-                                *base_vertex,
-                                instances.clone(),
-                            );
-                        }
-                        RenderCommand::Draw {
-                            vertices,
-                            instances,
-                        } => {
-                            render_pass.draw(vertices.clone(), instances.clone());
-                        }
+            render_context.begin_pass(&desc, render_resource_bindings, &mut |render_pass| {
+                for shadow_light in &shadow_lights {
+                    for (view, rect) in shadow_light.views.iter().zip(&shadow_light.rects) {
+                        set_viewport(render_pass, rect);
+                        replay_render_commands(render_pass, &view.translucent_draw, pipelines);
+                    }
+                }
+            });
+
+            let desc = PassDescriptor {
+                color_attachments: Vec::new(),
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    attachment: TextureAttachment::Id(transmittance_depth_texture),
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+                sample_count: 1,
+            };
+
+            render_context.begin_pass(&desc, render_resource_bindings, &mut |render_pass| {
+                for shadow_light in &shadow_lights {
+                    for (view, rect) in shadow_light.views.iter().zip(&shadow_light.rects) {
+                        set_viewport(render_pass, rect);
+                        replay_render_commands(
+                            render_pass,
+                            &view.translucent_depth_draw,
+                            pipelines,
+                        );
                     }
                 }
             });
         }
+    }
+}
 
-        if let Some(RenderResourceId::Texture(array_texture)) = input.get(Self::TEXTURE) {
-            for (i, texture) in self.textures.iter().enumerate() {
-                render_context.copy_texture_to_texture(
-                    *texture,
-                    [0, 0, 0],
-                    0,
-                    array_texture,
-                    [0, 0, i as u32],
-                    0,
-                    self.extent,
-                );
-            }
+/// Pipelines used to render the shadow maps.
+#[derive(Default)]
+pub struct ShadowPassPipelines {
+    depth: Handle<PipelineDescriptor>,
+    /// Used for casters with a [`ShadowAlphaMask`].
+    masked: Handle<PipelineDescriptor>,
+    /// Used for directional lights if the filter is moment based.
+    moments: Option<Handle<PipelineDescriptor>>,
+    /// Used for directional lights and casters with a [`ShadowAlphaMask`]
+    /// if the filter is moment based.
+    masked_moments: Option<Handle<PipelineDescriptor>>,
+    /// Used for directional lights and casters with [`ShadowTranslucent`]
+    /// if translucent shadows are enabled.
+    translucent: Option<Handle<PipelineDescriptor>>,
+}
+
+impl ShadowPassPipelines {
+    fn get(&self, kind: LightKind, masked: bool) -> &Handle<PipelineDescriptor> {
+        match (kind, &self.moments, &self.masked_moments, masked) {
+            (LightKind::Directional, Some(moments), _, false) => moments,
+            (LightKind::Directional, _, Some(masked_moments), true) => masked_moments,
+            (_, _, _, false) => &self.depth,
+            (_, _, _, true) => &self.masked,
         }
     }
+
+    /// True if the shadow maps of `kind` only store depth, so [`ShadowPipeline`]s can render them.
+    fn depth_only(&self, kind: LightKind) -> bool {
+        kind != LightKind::Directional || self.moments.is_none()
+    }
 }
 
 impl SystemNode for ShadowPassNode {
     fn get_system(&self) -> BoxedSystem {
         Box::new(shadow_pass_system.system().config(|config| {
-            config.0 = Some(RenderPipeline::new(SHADOW_PIPELINE.typed()));
+            config.0 = Some(ShadowPassPipelines {
+                depth: SHADOW_PIPELINE.typed(),
+                masked: SHADOW_MASKED_PIPELINE.typed(),
+                moments: self.moments.map(|_| SHADOW_MOMENTS_PIPELINE.typed()),
+                masked_moments: self.moments.map(|_| SHADOW_MASKED_MOMENTS_PIPELINE.typed()),
+                translucent: Some(SHADOW_TRANSLUCENT_PIPELINE.typed()).filter(|_| self.translucent),
+            });
         }))
     }
 }
 
 fn shadow_pass_system(
-    pipeline: Local<RenderPipeline>,
+    pipelines: Local<ShadowPassPipelines>,
     mut draw_context: DrawContext,
     mut lights: ResMut<ShadowLights>,
     mut render_resource_bindings: ResMut<RenderResourceBindings>,
     meshes: Res<Assets<Mesh>>,
-    mut query: Query<(&Handle<Mesh>, &mut RenderPipelines), Without<Shadowless>>,
+    cameras: Query<(&Camera, &GlobalTransform, &PerspectiveProjection)>,
+    mut query: Query<
+        (
+            &Handle<Mesh>,
+            &mut RenderPipelines,
+            Option<&GlobalTransform>,
+            Option<&sphere::BSphere>,
+            Option<&ShadowProxy>,
+            Option<&ShadowAlphaMask>,
+            Option<&ShadowTranslucent>,
+            Option<&ShadowPipeline>,
+        ),
+        (Without<Shadowless>, Without<NotShadowCaster>),
+    >,
 ) {
+    let camera = cameras
+        .iter()
+        .find(|(camera, _, _)| camera.name.as_deref() == Some(CAMERA_3D))
+        .map(|(_, transform, projection)| ShadowCamera::new(transform, projection));
+    let cull_casters = lights.cull_casters;
+
     for light in lights.lights.values_mut() {
-        light.draw.render_commands.clear();
+        let mut stats = ShadowCasterStats::default();
 
-        for (mesh_handle, mut render_pipelines) in query.iter_mut() {
-            let mesh = if let Some(mesh) = meshes.get(mesh_handle) {
-                mesh
-            } else {
-                continue;
-            };
+        for view in light.views.iter_mut() {
+            view.draw.render_commands.clear();
+            view.translucent_draw.render_commands.clear();
+            view.translucent_depth_draw.render_commands.clear();
 
-            if light.bindings.get("ViewProj").is_some() {
-                let mut pipeline_specialization =
-                    render_pipelines.pipelines[0].specialization.clone();
-                pipeline_specialization
-                    .dynamic_bindings
-                    .insert("ViewProj".to_string());
-                pipeline_specialization
-                    .dynamic_bindings
-                    .insert("Transform".to_string());
-                pipeline_specialization.primitive_topology = mesh.primitive_topology();
-                pipeline_specialization.vertex_buffer_layout = mesh.get_vertex_buffer_layout();
-                pipeline_specialization.sample_count = 1;
-
-                let bindings = &mut [
-                    &mut light.bindings,
-                    &mut render_pipelines.bindings,
-                    &mut render_resource_bindings,
-                ];
-
-                draw_context
-                    .set_pipeline(
-                        &mut light.draw,
-                        &pipeline.pipeline,
-                        &pipeline_specialization,
-                    )
-                    .unwrap();
-
-                draw_context
-                    .set_bind_groups_from_bindings(&mut light.draw, bindings)
-                    .unwrap();
-
-                if let Some(RenderResourceId::Buffer(index_buffer_resource)) = draw_context
-                    .render_resource_context
-                    .get_asset_resource(mesh_handle, INDEX_BUFFER_ASSET_INDEX)
-                {
-                    let index_format = mesh.indices().unwrap().into();
+            let frustum = Frustum::from_view_proj(&view.view_proj);
+            let extruded = camera.and_then(|camera| {
+                Frustum::extruded(&view.view_proj, &camera, camera.near, camera.far)
+            });
 
-                    light
-                        .draw
-                        .set_index_buffer(index_buffer_resource, 0, index_format);
+            for (
+                mesh_handle,
+                mut render_pipelines,
+                transform,
+                bsphere,
+                proxy,
+                mask,
+                translucent,
+                custom_pipeline,
+            ) in query.iter_mut()
+            {
+                // without translucent shadows translucent casters are opaque
+                let translucent = translucent.and(pipelines.translucent.as_ref());
+
+                if translucent.is_some() && light.kind != LightKind::Directional {
+                    continue;
                 }
 
-                if let Some(RenderResourceId::Buffer(vertex_attribute_buffer_resource)) =
-                    draw_context
-                        .render_resource_context
-                        .get_asset_resource(mesh_handle, VERTEX_ATTRIBUTE_BUFFER_ID)
-                {
-                    light
-                        .draw
-                        .set_vertex_buffer(0, vertex_attribute_buffer_resource, 0);
+                let mesh_handle = proxy.map_or(mesh_handle, |proxy| &proxy.0);
+                let mesh = if let Some(mesh) = meshes.get(mesh_handle) {
+                    mesh
+                } else {
+                    continue;
+                };
+
+                // casters without bounds are always drawn
+                let bounds = transform.zip(bsphere).filter(|_| cull_casters);
+                if let Some((transform, bsphere)) = bounds {
+                    let center = bsphere.origin(*transform);
+                    let radius = bsphere.radius(transform);
+
+                    if !frustum.intersects_sphere(center, radius) {
+                        stats.frustum_culled += 1;
+                        continue;
+                    }
+
+                    if let Some(extruded) = &extruded {
+                        if !extruded.intersects_sphere(center, radius) {
+                            stats.volume_culled += 1;
+                            continue;
+                        }
+                    }
                 }
 
-                let index_range = match mesh.indices() {
-                    Some(Indices::U16(indices)) => Some(0..indices.len() as u32),
-                    Some(Indices::U32(indices)) => Some(0..indices.len() as u32),
-                    None => None,
-                };
+                if view.bindings.get("ViewProj").is_some() {
+                    let mut pipeline_specialization =
+                        render_pipelines.pipelines[0].specialization.clone();
+                    pipeline_specialization
+                        .dynamic_bindings
+                        .insert("ViewProj".to_string());
+                    pipeline_specialization
+                        .dynamic_bindings
+                        .insert("Transform".to_string());
+                    pipeline_specialization.primitive_topology = mesh.primitive_topology();
+                    pipeline_specialization.vertex_buffer_layout = mesh.get_vertex_buffer_layout();
+                    pipeline_specialization.sample_count = 1;
+
+                    // without joint matrices the mesh is drawn in its bind pose
+                    if mesh.attribute(ATTRIBUTE_JOINT_WEIGHT).is_some()
+                        && mesh.attribute(ATTRIBUTE_JOINT_INDEX).is_some()
+                        && render_pipelines.bindings.get(JOINT_TRANSFORMS).is_some()
+                    {
+                        pipeline_specialization
+                            .shader_specialization
+                            .shader_defs
+                            .insert("SHADOW_SKINNED".to_string());
+                    }
 
-                if let Some(indices) = index_range.clone() {
-                    light.draw.draw_indexed(indices, 0, 0..1);
-                } else {
-                    light.draw.draw(0..mesh.count_vertices() as u32, 0..1);
+                    if let Some(translucent) = translucent {
+                        record_draw(
+                            &mut draw_context,
+                            &mut view.translucent_draw,
+                            translucent,
+                            &pipeline_specialization,
+                            &mut [
+                                &mut view.bindings,
+                                &mut render_pipelines.bindings,
+                                &mut render_resource_bindings,
+                            ],
+                            mesh_handle,
+                            mesh,
+                        );
+
+                        // receivers are only tinted behind the closest translucent caster
+                        let depth_pipeline = if mask.is_some() {
+                            &pipelines.masked
+                        } else {
+                            &pipelines.depth
+                        };
+
+                        record_draw(
+                            &mut draw_context,
+                            &mut view.translucent_depth_draw,
+                            depth_pipeline,
+                            &pipeline_specialization,
+                            &mut [
+                                &mut view.bindings,
+                                &mut render_pipelines.bindings,
+                                &mut render_resource_bindings,
+                            ],
+                            mesh_handle,
+                            mesh,
+                        );
+                    } else {
+                        let pipeline = custom_pipeline
+                            .filter(|_| pipelines.depth_only(light.kind))
+                            .map_or_else(
+                                || pipelines.get(light.kind, mask.is_some()),
+                                |custom_pipeline| &custom_pipeline.0,
+                            );
+
+                        record_draw(
+                            &mut draw_context,
+                            &mut view.draw,
+                            pipeline,
+                            &pipeline_specialization,
+                            &mut [
+                                &mut view.bindings,
+                                &mut render_pipelines.bindings,
+                                &mut render_resource_bindings,
+                            ],
+                            mesh_handle,
+                            mesh,
+                        );
+                    }
+
+                    stats.drawn += 1;
                 }
             }
         }
+
+        light.caster_stats = stats;
+    }
+}
+
+/// Records drawing a mesh with a shadow pipeline into `draw`.
+fn record_draw(
+    draw_context: &mut DrawContext,
+    draw: &mut Draw,
+    pipeline: &Handle<PipelineDescriptor>,
+    pipeline_specialization: &PipelineSpecialization,
+    bindings: &mut [&mut RenderResourceBindings],
+    mesh_handle: &Handle<Mesh>,
+    mesh: &Mesh,
+) {
+    draw_context
+        .set_pipeline(draw, pipeline, pipeline_specialization)
+        .unwrap();
+
+    draw_context
+        .set_bind_groups_from_bindings(draw, bindings)
+        .unwrap();
+
+    if let Some(RenderResourceId::Buffer(index_buffer_resource)) = draw_context
+        .render_resource_context
+        .get_asset_resource(mesh_handle, INDEX_BUFFER_ASSET_INDEX)
+    {
+        let index_format = mesh.indices().unwrap().into();
+
+        draw.set_index_buffer(index_buffer_resource, 0, index_format);
+    }
+
+    if let Some(RenderResourceId::Buffer(vertex_attribute_buffer_resource)) = draw_context
+        .render_resource_context
+        .get_asset_resource(mesh_handle, VERTEX_ATTRIBUTE_BUFFER_ID)
+    {
+        draw.set_vertex_buffer(0, vertex_attribute_buffer_resource, 0);
+    }
+
+    let index_range = match mesh.indices() {
+        Some(Indices::U16(indices)) => Some(0..indices.len() as u32),
+        Some(Indices::U32(indices)) => Some(0..indices.len() as u32),
+        None => None,
+    };
+
+    if let Some(indices) = index_range {
+        draw.draw_indexed(indices, 0, 0..1);
+    } else {
+        draw.draw(0..mesh.count_vertices() as u32, 0..1);
     }
 }
@@ -0,0 +1,93 @@
+use crate::shadow_pass_node::*;
+use bevy::prelude::*;
+
+const NEAR: f32 = 0.1;
+const MIN_BIAS: f32 = 0.01;
+const MAX_BIAS: f32 = 0.05;
+/// Widest field of view of the shadow map, a perspective projection of π or more is degenerate.
+const MAX_FOV: f32 = std::f32::consts::PI - 0.01;
+
+/// A light shining from the entity's [`GlobalTransform`] along its forward (-Z) axis in a cone.
+/// Spot lights are shaded by the shadow pbr pipeline only.
+pub struct SpotLight {
+    pub color: Color,
+    pub intensity: f32,
+    pub range: f32,
+    /// Angle from the center of the cone in radians where the light starts to fall off.
+    pub inner_angle: f32,
+    /// Angle from the center of the cone in radians beyond which nothing is lit. The shadow map
+    /// covers cones up to just below a half angle of π/2, wider cones are only partially shadowed.
+    pub outer_angle: f32,
+}
+
+impl Default for SpotLight {
+    fn default() -> Self {
+        Self {
+            color: Color::rgb(1.0, 1.0, 1.0),
+            intensity: 200.0,
+            range: 20.0,
+            inner_angle: 0.0,
+            outer_angle: std::f32::consts::FRAC_PI_4,
+        }
+    }
+}
+
+pub struct ShadowSpotLight {
+    /// Near plane of projection.
+    pub near: f32,
+    /// Min/max bias in world units used when comparing the fragment's distance to the light
+    /// with the shadow map distance.
+    pub bias: Vec2,
+    /// Resolution of the shadow map in the shadow atlas,
+    /// if None [`crate::ShadowPlugin::spot_light_resolution`] is used.
+    pub resolution: Option<u32>,
+}
+
+impl Default for ShadowSpotLight {
+    fn default() -> Self {
+        Self {
+            near: NEAR,
+            bias: Vec2::new(MIN_BIAS, MAX_BIAS),
+            resolution: None,
+        }
+    }
+}
+
+impl Light for SpotLight {
+    type Config = ShadowSpotLight;
+
+    const KIND: LightKind = LightKind::Spot;
+
+    fn proj_matrix(&self, config: Option<&Self::Config>) -> Mat4 {
+        let near_far = self.depth_range(config);
+
+        let fov = (2.0 * self.outer_angle).min(MAX_FOV);
+
+        Mat4::perspective_rh(fov, 1.0, near_far.x, near_far.y)
+    }
+
+    fn view_matrix(&self, transform: &GlobalTransform, _config: Option<&Self::Config>) -> Mat4 {
+        // ignore scale, it would distort the projection
+        Mat4::from_rotation_translation(transform.rotation, transform.translation).inverse()
+    }
+
+    fn shadow_bias_min_max(&self, config: Option<&Self::Config>) -> Vec2 {
+        config.map_or(Vec2::new(MIN_BIAS, MAX_BIAS), |config| config.bias)
+    }
+
+    fn shadow_resolution(&self, config: Option<&Self::Config>) -> Option<u32> {
+        config.and_then(|config| config.resolution)
+    }
+
+    fn depth_range(&self, config: Option<&Self::Config>) -> Vec2 {
+        Vec2::new(config.map_or(NEAR, |config| config.near), self.range)
+    }
+
+    fn shading(&self) -> Option<LightShading> {
+        Some(LightShading {
+            color: Vec4::from(self.color.as_rgba_linear()) * self.intensity,
+            range: self.range,
+            cos_inner_outer: Vec2::new(self.inner_angle.cos(), self.outer_angle.cos()),
+        })
+    }
+}